@@ -0,0 +1,154 @@
+use chrono::{DateTime, Local, NaiveDate};
+use std::path::{Path, PathBuf};
+
+use crate::task::{Task, TaskRepository};
+
+/// Archive file for tasks archived on `date`, e.g. `taiginator.archive-2024-03-20.md`
+/// next to `taiginator.md`.
+pub fn archive_path(task_file: &Path, date: NaiveDate) -> PathBuf {
+    task_file.with_file_name(format!(
+        "{}{}.md",
+        archive_prefix(task_file),
+        date.format("%Y-%m-%d")
+    ))
+}
+
+/// All archive files belonging to `task_file`, oldest first.
+pub fn archive_files(task_file: &Path) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
+    let dir = match task_file.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    let prefix = archive_prefix(task_file);
+    let mut files: Vec<PathBuf> = std::fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| {
+            path.file_name()
+                .map(|n| n.to_string_lossy())
+                .is_some_and(|n| n.starts_with(&prefix) && n.ends_with(".md"))
+        })
+        .collect();
+    // The date in the name sorts chronologically.
+    files.sort();
+    Ok(files)
+}
+
+/// Whether a completed task is old enough to be archived.
+///
+/// Without a cutoff every completed task qualifies. With one, the task has to have
+/// been completed before it. Tasks checked off before completion times were recorded
+/// fall back to their latest scheduled or due date; undated ones are kept.
+pub fn is_archivable(task: &Task, cutoff: Option<DateTime<Local>>) -> bool {
+    if !task.is_complete {
+        return false;
+    }
+    let Some(cutoff) = cutoff else {
+        return true;
+    };
+    let finished = task
+        .completed_at
+        .or_else(|| task.scheduled.into_iter().chain(task.due).max());
+    finished.is_some_and(|date| date < cutoff)
+}
+
+/// Moves `ids` from `repo` into today's archive file.
+///
+/// The archive is written before the caller saves `repo`, so a crash in between
+/// leaves a task in both files rather than in neither.
+pub fn archive_tasks(
+    repo: &mut TaskRepository,
+    ids: &[u32],
+    task_file: &Path,
+) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let path = archive_path(task_file, Local::now().date_naive());
+    let mut archive = TaskRepository::load_from_file(&path)?;
+
+    // Take copies first: removing a parent reparents its subtasks, and we want them
+    // to stay nested in the archive.
+    let tasks: Vec<Task> = ids.iter().filter_map(|id| repo.get(*id).cloned()).collect();
+    for task in tasks {
+        repo.remove(task.id);
+        archive.insert(task);
+    }
+
+    archive.save_to_file(&path)?;
+    Ok(path)
+}
+
+fn archive_prefix(task_file: &Path) -> String {
+    let stem = task_file
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    format!("{}.archive-", stem)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::task::tests::temp_task_file;
+    use chrono::TimeZone;
+
+    fn day(d: u32) -> DateTime<Local> {
+        Local.with_ymd_and_hms(2024, 3, d, 0, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn older_than_cutoff_falls_back_to_scheduled_and_due() {
+        let cutoff = Some(day(15));
+        let done = |task: Task| Task {
+            is_complete: true,
+            ..task
+        };
+
+        assert!(!is_archivable(&Task::new("Open".to_string()), None));
+        assert!(is_archivable(&done(Task::new("Done".to_string())), None));
+
+        let finished_on = |d: u32| {
+            let mut task = Task::new("Finished".to_string());
+            task.complete(day(d));
+            task
+        };
+        assert!(is_archivable(&finished_on(10), cutoff));
+        assert!(!is_archivable(&finished_on(20), cutoff));
+
+        // Checked off before completion times were kept: the latest date decides.
+        let old = done(Task::new("Old".to_string()).scheduled(Some(day(1))));
+        assert!(is_archivable(&old, cutoff));
+        let due_later = done(
+            Task::new("Due later".to_string())
+                .scheduled(Some(day(1)))
+                .due(Some(day(18))),
+        );
+        assert!(!is_archivable(&due_later, cutoff));
+        assert!(!is_archivable(
+            &done(Task::new("Undated".to_string())),
+            cutoff
+        ));
+    }
+
+    #[test]
+    fn subtasks_stay_nested_in_the_archive() {
+        let path = temp_task_file("archive");
+        let mut repo = TaskRepository::new();
+        let release = repo.add(Task::new("Release".to_string()));
+        let tag = repo.add(Task::new("Tag".to_string()).parent(Some(release)));
+        let other = repo.add(Task::new("Other".to_string()));
+
+        let archive_file = archive_tasks(&mut repo, &[release, tag], &path).unwrap();
+        assert!(repo.get(release).is_none() && repo.get(tag).is_none());
+        assert!(repo.get(other).is_some());
+        assert_eq!(archive_files(&path).unwrap(), vec![archive_file.clone()]);
+
+        let archive = TaskRepository::load_from_file(&archive_file).unwrap();
+        assert_eq!(archive.get(tag).unwrap().parent, Some(release));
+        let text = std::fs::read_to_string(&archive_file).unwrap();
+        assert!(text.contains(&format!("\n  [ID:{}] - [ ] Tag", tag)));
+
+        std::fs::remove_dir_all(path.parent().unwrap()).ok();
+    }
+}
@@ -1,5 +1,9 @@
 use clap::{Arg, ArgAction, Command, Parser, Subcommand};
 
+use crate::ids::IdRange;
+use crate::recurrence::Recurrence;
+use crate::task::Priority;
+
 #[derive(Parser)]
 #[command(version = "0.1")]
 #[command(about = "A taigination of task manager.")]
@@ -49,45 +53,235 @@ pub fn cli() -> Command {
                         .num_args(1..)
                         .required(true),
                 )
+                .arg(
+                    Arg::new("PRIORITY")
+                        .help("Priority of the task (high, medium, low)")
+                        .short('p')
+                        .long("priority")
+                        .action(ArgAction::Set)
+                        .value_parser(clap::value_parser!(Priority)),
+                )
+                .arg(repeat_arg())
+                .arg(
+                    Arg::new("PARENT")
+                        .help("Adds the task as a subtask of this task ID")
+                        .long("parent")
+                        .action(ArgAction::Set)
+                        .value_parser(clap::value_parser!(u32)),
+                )
+                .arg(
+                    Arg::new("DEPENDS_ON")
+                        .help("Task IDs that have to be done first")
+                        .long("depends-on")
+                        .action(ArgAction::Set)
+                        .num_args(1..)
+                        .value_parser(clap::value_parser!(u32)),
+                )
                 .arg_required_else_help(true)
-                .subcommand(
-                    Command::new("when").about("Schedules a task").arg(
-                        Arg::new("SCHEDULED")
-                            .help("Schedules a task for certain date")
-                            .action(ArgAction::Set)
-                            .num_args(1..)
-                            .required(false),
-                    ),
+                .subcommand(when_command().subcommand(due_command()))
+                .subcommand(due_command().subcommand(when_command())),
+        )
+        .subcommand(
+            Command::new("edit")
+                .about("Edits a task, opens it in $EDITOR when nothing else is given")
+                .subcommand_precedence_over_arg(true)
+                .arg(ids_arg("Task IDs to be edited, comma separated (e.g. 3,5,7-10).").num_args(1))
+                .arg(filter_arg())
+                .arg(
+                    Arg::new("TITLE")
+                        .help("New title for task")
+                        .action(ArgAction::Set)
+                        .num_args(1..)
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("NEW_TITLE")
+                        .help("New title, for use with --filter")
+                        .long("title")
+                        .action(ArgAction::Set)
+                        .conflicts_with("TITLE"),
+                )
+                .arg(
+                    Arg::new("UNSCHEDULE")
+                        .help("Clears the scheduled date")
+                        .long("unschedule")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(repeat_arg())
+                .arg(
+                    Arg::new("NO_REPEAT")
+                        .help("Stops the task from repeating")
+                        .long("no-repeat")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("NO_DUE")
+                        .help("Clears the due date")
+                        .long("no-due")
+                        .action(ArgAction::SetTrue),
+                )
+                .subcommand(when_command().subcommand(due_command()))
+                .subcommand(due_command().subcommand(when_command())),
+        )
+        .subcommand(
+            Command::new("list")
+                .about("Lists tasks")
+                .arg(
+                    Arg::new("STATE")
+                        .help("Filter query, e.g. open and (+work or due.before:friday)")
+                        .action(ArgAction::Set)
+                        .num_args(1..)
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("ARCHIVED")
+                        .help("Searches archived tasks instead")
+                        .long("archived")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("SORT")
+                        .help("Orders tasks by this field instead of by priority")
+                        .long("sort")
+                        .action(ArgAction::Set)
+                        .value_parser(["priority", "id", "title", "scheduled", "due", "created"]),
+                )
+                .arg(
+                    Arg::new("DESC")
+                        .help("Sorts in descending order")
+                        .long("desc")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("OLDEST")
+                        .help("Shows the oldest tasks first, same as --sort created")
+                        .long("oldest")
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with("SORT"),
+                )
+                .arg(
+                    Arg::new("GROUP_BY")
+                        .help("Splits the list under headings")
+                        .long("group-by")
+                        .action(ArgAction::Set)
+                        .value_parser(["date", "status"]),
+                )
+                .arg(
+                    Arg::new("OLDER_THAN")
+                        .help("Only shows tasks created more than this many days ago")
+                        .long("older-than")
+                        .value_name("DAYS")
+                        .action(ArgAction::Set)
+                        .value_parser(clap::value_parser!(u32)),
+                ),
+        )
+        .subcommand(
+            Command::new("archive")
+                .about("Moves completed tasks to a dated archive file.")
+                .arg(
+                    Arg::new("OLDER_THAN")
+                        .help("Only archive tasks completed more than this many days ago.")
+                        .long("older-than")
+                        .value_name("DAYS")
+                        .action(ArgAction::Set)
+                        .value_parser(clap::value_parser!(u32)),
+                ),
+        )
+        .subcommand(
+            Command::new("check")
+                .about("Checks task completed.")
+                .arg(ids_arg("Task IDs to be checked complete, e.g. 3 5 7-10."))
+                .arg(filter_arg())
+                .arg(
+                    Arg::new("REOPEN")
+                        .help("Marks the tasks open again instead")
+                        .long("reopen")
+                        .action(ArgAction::SetTrue),
                 ),
         )
         .subcommand(
-            Command::new("list").about("Lists tasks").arg(
-                Arg::new("STATE")
-                    .help("Filter task by state")
-                    .action(ArgAction::Set)
-                    .num_args(1..)
-                    .required(false),
-            ),
+            Command::new("depend")
+                .about("Makes a task wait for other tasks.")
+                .arg(
+                    Arg::new("ID")
+                        .help("Task ID that has to wait.")
+                        .action(ArgAction::Set)
+                        .num_args(1)
+                        .required(true)
+                        .value_parser(clap::value_parser!(u32)),
+                )
+                .arg(
+                    Arg::new("ON")
+                        .help("Task IDs it has to wait for.")
+                        .action(ArgAction::Set)
+                        .num_args(1..)
+                        .value_delimiter(',')
+                        .required(true)
+                        .value_parser(clap::value_parser!(IdRange)),
+                )
+                .arg(
+                    Arg::new("REMOVE")
+                        .help("Removes the dependencies instead.")
+                        .long("remove")
+                        .action(ArgAction::SetTrue),
+                ),
         )
         .subcommand(
-            Command::new("check").about("Checks task completed.").arg(
-                Arg::new("ID")
-                    .help("Task ID to be checked complete.")
-                    .action(ArgAction::Set)
-                    .num_args(1)
-                    .required(true)
-                    .value_parser(clap::value_parser!(u32)),
-            ),
+            Command::new("priority")
+                .about("Changes priority of a task.")
+                // Clap can't have optional IDs in front of a required level, so both
+                // come in one list and the last value is the level.
+                .arg(
+                    Arg::new("ID_LEVEL")
+                        .help("Task IDs to be reprioritized, then the new priority (high, medium, low or none to clear).")
+                        .value_name("ID|LEVEL")
+                        .action(ArgAction::Set)
+                        .num_args(1..)
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("FILTER")
+                        .help("Applies to every task matching these list filters, e.g. \"+work open\"")
+                        .long("filter")
+                        .action(ArgAction::Set),
+                ),
+        )
+        .subcommand(
+            Command::new("remove")
+                .about("Moves a task to the trash")
+                .arg(ids_arg("Task IDs to be removed"))
+                .arg(filter_arg()),
+        )
+        .subcommand(
+            Command::new("trash")
+                .about("Shows or restores removed tasks.")
+                .subcommand_required(true)
+                .arg_required_else_help(true)
+                .subcommand(Command::new("list").about("Lists removed tasks."))
+                .subcommand(
+                    Command::new("restore")
+                        .about("Puts a removed task back.")
+                        .arg(
+                            Arg::new("ID")
+                                .help("Task IDs to be restored.")
+                                .action(ArgAction::Set)
+                                .num_args(1..)
+                                .value_delimiter(',')
+                                .required(true)
+                                .value_parser(clap::value_parser!(IdRange)),
+                        ),
+                )
+                .subcommand(Command::new("empty").about("Deletes removed tasks for good.")),
         )
         .subcommand(
-            Command::new("remove").about("Removes a task").arg(
-                Arg::new("ID")
-                    .help("Task ID to be removed")
-                    .action(ArgAction::Set)
-                    .num_args(1..)
-                    .required(true)
-                    .value_parser(clap::value_parser!(u32)),
-            ),
+            Command::new("undo")
+                .about("Reverts the last change to your tasks.")
+                .arg(steps_arg("How many changes to revert.")),
+        )
+        .subcommand(
+            Command::new("redo")
+                .about("Re-applies the last undone change.")
+                .arg(steps_arg("How many changes to re-apply.")),
         )
         .subcommand(
             Command::new("pomo")
@@ -117,13 +311,122 @@ pub fn cli() -> Command {
                                 .action(ArgAction::Set)
                                 .num_args(1)
                                 .required(true),
+                        )
+                        .arg(
+                            Arg::new("LONG_BREAK")
+                                .help("How long should long break session last.")
+                                .long("long-break")
+                                .value_name("MIN")
+                                .action(ArgAction::Set)
+                                .default_value("15")
+                                .value_parser(clap::value_parser!(u64)),
+                        )
+                        .arg(
+                            Arg::new("LONG_BREAK_EVERY")
+                                .help("Take a long break after every N focus sessions (0 for never).")
+                                .long("long-break-every")
+                                .value_name("N")
+                                .action(ArgAction::Set)
+                                .default_value("4")
+                                .value_parser(clap::value_parser!(u32)),
+                        )
+                        .arg(
+                            Arg::new("TASK")
+                                .help("Task ID the session is spent on.")
+                                .short('t')
+                                .long("task")
+                                .action(ArgAction::Set)
+                                .value_parser(clap::value_parser!(u32)),
                         ),
                 )
                 .subcommand(Command::new("status").about("Shows status of running session."))
                 .subcommand(Command::new("stop").about("Stops running pomodoro session."))
                 .subcommand(Command::new("pause").about("Pauses running pomodoro session."))
                 .subcommand(Command::new("resume").about("Resumes paused pomodoro session."))
-                .subcommand(Command::new("kill").about("Kills daemon.")),
+                .subcommand(Command::new("kill").about("Kills daemon."))
+                .subcommand(
+                    Command::new("stats")
+                        .about("Shows focus time, streaks and time per task.")
+                        .arg(
+                            Arg::new("CHART")
+                                .help("Adds a bar chart of focus time per day.")
+                                .long("chart")
+                                .action(ArgAction::SetTrue),
+                        )
+                        .arg(
+                            Arg::new("DAYS")
+                                .help("How many days the chart covers.")
+                                .long("days")
+                                .action(ArgAction::Set)
+                                .default_value("14")
+                                .value_parser(clap::value_parser!(u32).range(1..)),
+                        ),
+                ),
         )
         .subcommand(Command::new("daemon").hide(true))
 }
+
+// `when` and `due` can follow each other in either order, so each one is also
+// nested under the other.
+fn when_command() -> Command {
+    Command::new("when")
+        .about("Schedules a task")
+        .subcommand_precedence_over_arg(true)
+        .arg(
+            Arg::new("SCHEDULED")
+                .help("Schedules a task for certain date")
+                .action(ArgAction::Set)
+                .num_args(1..)
+                .required(false),
+        )
+}
+
+fn due_command() -> Command {
+    Command::new("due")
+        .about("Sets a deadline")
+        .subcommand_precedence_over_arg(true)
+        .arg(
+            Arg::new("DUE")
+                .help("Date the task has to be done by")
+                .action(ArgAction::Set)
+                .num_args(1..)
+                .required(false),
+        )
+}
+
+fn repeat_arg() -> Arg {
+    Arg::new("REPEAT")
+        .help("Repeats the task, e.g. \"monday\", \"2 weeks\", \"first of the month\"")
+        .short('r')
+        .long("every")
+        .action(ArgAction::Set)
+        .value_parser(clap::value_parser!(Recurrence))
+}
+
+fn steps_arg(help: &'static str) -> Arg {
+    Arg::new("STEPS")
+        .help(help)
+        .action(ArgAction::Set)
+        .default_value("1")
+        .value_parser(clap::value_parser!(u32).range(1..))
+}
+
+/// Task IDs and ranges such as `3 5 7-10`; commas work too.
+fn ids_arg(help: &'static str) -> Arg {
+    Arg::new("ID")
+        .help(help)
+        .action(ArgAction::Set)
+        .num_args(1..)
+        .value_delimiter(',')
+        .required_unless_present("FILTER")
+        .value_parser(clap::value_parser!(IdRange))
+}
+
+/// Picks the tasks with the same words `taiga list` takes instead of by ID.
+fn filter_arg() -> Arg {
+    Arg::new("FILTER")
+        .help("Applies to every task matching these list filters, e.g. \"+shopping open\"")
+        .long("filter")
+        .action(ArgAction::Set)
+        .conflicts_with("ID")
+}
@@ -1,4 +1,4 @@
-use crate::ipc::{DaemonCommand, DaemonResponse, get_socket_path};
+use crate::ipc::{DaemonCommand, DaemonResponse, get_socket_path, read_message, write_message};
 use interprocess::local_socket::traits::tokio::Stream as _;
 use interprocess::local_socket::{
     GenericFilePath, GenericNamespaced, ToFsName, ToNsName, tokio::Stream,
@@ -6,7 +6,6 @@ use interprocess::local_socket::{
 use std::error::Error;
 use std::process::{Command, Stdio};
 use std::time::Duration;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
 pub async fn send_command(cmd: DaemonCommand) -> Result<DaemonResponse, Box<dyn Error>> {
     let socket_path = get_socket_path();
@@ -24,19 +23,11 @@ pub async fn send_command(cmd: DaemonCommand) -> Result<DaemonResponse, Box<dyn
         }
     };
 
-    let req_bytes = serde_json::to_vec(&cmd)?;
-    stream.write_all(&req_bytes).await?;
+    write_message(&mut stream, &cmd).await?;
 
-    let mut buffer = [0; 1024];
-    let n = stream.read(&mut buffer).await?;
-
-    if n == 0 {
-        return Err("Daemon closed connection without response".into());
-    }
-
-    let resp: DaemonResponse = serde_json::from_slice(&buffer[0..n])?;
-
-    Ok(resp)
+    read_message(&mut stream)
+        .await?
+        .ok_or_else(|| "Daemon closed connection without response".into())
 }
 
 // Helper: Handle OS-specific naming
@@ -5,6 +5,10 @@ use std::path::PathBuf;
 pub struct Config {
     pub data_directory: String,
     pub task_filename: String,
+    /// Removed tasks older than this many days are purged from the trash.
+    /// Kept forever when unset.
+    #[serde(default)]
+    pub trash_retention_days: Option<u32>,
 }
 
 impl ::std::default::Default for Config {
@@ -18,6 +22,7 @@ impl ::std::default::Default for Config {
         Self {
             data_directory: default_path.to_string_lossy().to_string(),
             task_filename: "taiginator.md".to_string(),
+            trash_retention_days: None,
         }
     }
 }
@@ -1,45 +1,67 @@
-use crate::ipc::{DaemonCommand, DaemonResponse, get_socket_path};
-use interprocess::local_socket::traits::tokio::{Listener as _, Stream as _};
+use crate::history::{self, Outcome, Session};
+use crate::ipc::{DaemonCommand, DaemonResponse, get_socket_path, read_message, write_message};
+use chrono::{DateTime, Local};
+use interprocess::local_socket::traits::tokio::Listener as _;
 use interprocess::local_socket::{
     GenericFilePath, GenericNamespaced, ListenerOptions, ToFsName, ToNsName,
-    tokio::{Listener, Stream as LocalSocketStream},
+    tokio::Stream as LocalSocketStream,
 };
 use notify_rust::Notification;
+use serde::{Deserialize, Serialize};
 use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::sync::Mutex;
-use tokio::time::{self, Duration, Instant};
+use tokio::time::{self, Duration};
 
+#[derive(Serialize, Deserialize)]
 struct TimerConfig {
     focus_duration: Duration,
     break_duration: Duration,
+    #[serde(default)]
+    long_break_duration: Duration,
+    /// A long break follows every this many pomodoros; 0 means never.
+    #[serde(default)]
+    long_break_interval: u32,
 }
 
-// The Internal State of the Daemon
+// The Internal State of the Daemon, snapshotted to disk on every change
+#[derive(Serialize, Deserialize)]
 struct TimerState {
     mode: crate::ipc::PomoMode,
-    end_time: Option<Instant>,
+    /// Wall-clock rather than `Instant`, so it still means something after a restart.
+    end_time: Option<DateTime<Local>>,
     cycles_remaining: u32,
+    /// Pomodoros finished this session, to know when a long break is due.
+    #[serde(default)]
+    pomodoros_done: u32,
     config: Option<TimerConfig>,
     task_id: Option<u32>,
+    task_title: Option<String>,
     paused_duration: Option<Duration>,
+    /// Wall-clock start of the current focus or break, for the history log.
+    interval_start: Option<DateTime<Local>>,
+    #[serde(skip)]
+    history_path: PathBuf,
+    #[serde(skip)]
+    state_path: PathBuf,
 }
 
 pub async fn run_daemon() -> Result<(), Box<dyn Error>> {
     println!("Daemon starting...");
 
+    let cfg: crate::config::Config = confy::load("taiga", None)?;
+
     let socket_path = get_socket_path();
 
     // Clean up old socket file (Linux/Mac)
-    if !cfg!(windows) {
-        if std::fs::metadata(&socket_path).is_ok() {
-            println!("Removing old socket file...");
-            std::fs::remove_file(&socket_path).ok();
-        }
+    if !cfg!(windows) && std::fs::metadata(&socket_path).is_ok() {
+        println!("Removing old socket file...");
+        std::fs::remove_file(&socket_path).ok();
     }
 
-    let mut listener = if cfg!(windows) {
+    let listener = if cfg!(windows) {
         let name = socket_path.as_str().to_ns_name::<GenericNamespaced>()?;
         ListenerOptions::new().name(name).create_tokio()?
     } else {
@@ -49,14 +71,7 @@ pub async fn run_daemon() -> Result<(), Box<dyn Error>> {
 
     println!("Daemon listening at: {}", socket_path);
 
-    let state = Arc::new(Mutex::new(TimerState {
-        mode: crate::ipc::PomoMode::Idle,
-        end_time: None,
-        cycles_remaining: 0,
-        config: None,
-        task_id: None,
-        paused_duration: None,
-    }));
+    let state = Arc::new(Mutex::new(restore_state(&cfg.data_directory, Local::now())));
 
     let mut interval = time::interval(Duration::from_secs(1));
 
@@ -64,10 +79,11 @@ pub async fn run_daemon() -> Result<(), Box<dyn Error>> {
         tokio::select! {
             _ = interval.tick() => {
                 let mut locked_state = state.lock().await;
-                if let Some(end_time) = locked_state.end_time {
-                    if Instant::now() >= end_time {
-                        handle_timer_transition(&mut locked_state);
-                    }
+                if let Some(end_time) = locked_state.end_time
+                    && Local::now() >= end_time
+                {
+                    handle_timer_transition(&mut locked_state, end_time, true);
+                    save_state(&locked_state);
                 }
             }
 
@@ -92,56 +108,76 @@ async fn handle_connection(
     stream: &mut LocalSocketStream,
     state: Arc<Mutex<TimerState>>,
 ) -> Result<(), Box<dyn Error>> {
-    let mut buffer = [0; 1024];
-    let n = stream.read(&mut buffer).await?;
-
-    if n == 0 {
+    let Some(req) = read_message::<DaemonCommand>(stream).await? else {
         return Ok(());
-    }
-
-    let req: DaemonCommand = serde_json::from_slice(&buffer[0..n])?;
+    };
+    let changes_state = !matches!(req, DaemonCommand::Status | DaemonCommand::Ping);
 
     let response = {
         let mut locked = state.lock().await;
-        match req {
+        let response = match req {
             DaemonCommand::Start {
-                task_id: _, // You might want to store this in state if you track specific tasks
+                task_id,
+                task_title,
                 focus_len,
                 break_len,
+                long_break_len,
+                long_break_every,
                 cycles,
             } => {
+                record_interval(&locked, Outcome::Interrupted, Local::now());
+
                 let focus_dur = Duration::from_secs(focus_len * 60);
                 let break_dur = Duration::from_secs(break_len * 60);
 
                 locked.config = Some(TimerConfig {
                     focus_duration: focus_dur,
                     break_duration: break_dur,
+                    long_break_duration: Duration::from_secs(long_break_len * 60),
+                    long_break_interval: long_break_every,
                 });
 
                 locked.cycles_remaining = cycles;
+                locked.pomodoros_done = 0;
                 locked.mode = crate::ipc::PomoMode::Focus;
-                locked.end_time = Some(Instant::now() + focus_dur);
+                locked.end_time = Some(after(Local::now(), focus_dur));
                 locked.paused_duration = None;
+                locked.task_id = task_id;
+                locked.task_title = task_title;
+                locked.interval_start = Some(Local::now());
 
+                let on_task = match (locked.task_id, &locked.task_title) {
+                    (Some(id), Some(title)) => format!(" on #{} {}", id, title),
+                    _ => String::new(),
+                };
+                let long_break = if long_break_every > 0 {
+                    format!(
+                        ", {}m Long break every {}",
+                        long_break_len, long_break_every
+                    )
+                } else {
+                    String::new()
+                };
                 DaemonResponse::Ok(format!(
-                    "Started: {}m Focus, {}m Break ({} cycles)",
-                    focus_len, break_len, cycles
+                    "Started: {}m Focus, {}m Break{} ({} cycles){}",
+                    focus_len, break_len, long_break, cycles, on_task
                 ))
             }
             DaemonCommand::Stop => {
-                locked.end_time = None;
-                locked.task_id = None;
+                record_interval(&locked, Outcome::Stopped, Local::now());
+                reset_state(&mut locked);
                 DaemonResponse::Ok("Timer stopped".to_string())
             }
             DaemonCommand::Status => {
                 if let Some(end) = locked.end_time {
-                    let rem = end.saturating_duration_since(Instant::now()).as_secs();
+                    let rem = until(end, Local::now()).as_secs();
                     DaemonResponse::Status {
                         remaining_secs: rem,
                         is_running: true,
-                        mode: locked.mode, // Now returns "Focus" or "Break"
+                        mode: locked.mode, // Now returns "Focus", "Break" or "LongBreak"
                         cycles_left: locked.cycles_remaining,
                         task_id: locked.task_id,
+                        task_title: locked.task_title.clone(),
                     }
                 } else if let Some(dur) = locked.paused_duration {
                     DaemonResponse::Status {
@@ -150,6 +186,7 @@ async fn handle_connection(
                         mode: locked.mode, // Returns mode even while paused
                         cycles_left: locked.cycles_remaining,
                         task_id: locked.task_id,
+                        task_title: locked.task_title.clone(),
                     }
                 } else {
                     DaemonResponse::Status {
@@ -158,12 +195,13 @@ async fn handle_connection(
                         mode: crate::ipc::PomoMode::Idle,
                         cycles_left: 0,
                         task_id: locked.task_id,
+                        task_title: locked.task_title.clone(),
                     }
                 }
             }
             DaemonCommand::Pause => {
                 if let Some(end) = locked.end_time {
-                    let remaining = end.saturating_duration_since(Instant::now());
+                    let remaining = until(end, Local::now());
                     locked.paused_duration = Some(remaining);
                     locked.end_time = None;
                     DaemonResponse::Ok(format!("Paused with {}s remaining", remaining.as_secs()))
@@ -173,7 +211,7 @@ async fn handle_connection(
             }
             DaemonCommand::Resume => {
                 if let Some(duration) = locked.paused_duration {
-                    locked.end_time = Some(Instant::now() + duration);
+                    locked.end_time = Some(after(Local::now(), duration));
                     locked.paused_duration = None;
                     DaemonResponse::Ok("Timer resumed".to_string())
                 } else {
@@ -181,58 +219,85 @@ async fn handle_connection(
                 }
             }
             DaemonCommand::Kill => {
-                let _ = stream
-                    .write_all(&serde_json::to_vec(&DaemonResponse::Ok(
-                        "Daemon killing itself.".into(),
-                    ))?)
+                // The snapshot is already on disk; the next daemon resumes from it.
+                let _ = write_message(stream, &DaemonResponse::Ok("Daemon killing itself.".into()))
                     .await;
                 std::process::exit(0);
             }
             DaemonCommand::Ping => DaemonResponse::Pong,
+        };
+        if changes_state {
+            save_state(&locked);
         }
+        response
     };
 
-    let resp_bytes = serde_json::to_vec(&response)?;
-    stream.write_all(&resp_bytes).await?;
-
-    Ok(())
+    write_message(stream, &response).await
 }
 
-fn handle_timer_transition(state: &mut TimerState) {
-    let config = state.config.as_ref().unwrap(); // Should exist if running
+/// Moves on from the interval that ended at `at`. The next one is timed from
+/// `at` too, so a late tick doesn't stretch the session.
+fn handle_timer_transition(state: &mut TimerState, at: DateTime<Local>, announce: bool) {
+    record_interval(state, Outcome::Completed, at);
+    state.interval_start = Some(at);
+
+    let Some(config) = state.config.as_ref() else {
+        reset_state(state);
+        return;
+    };
 
     match state.mode {
         crate::ipc::PomoMode::Focus => {
-            state.cycles_remaining -= 1;
+            state.cycles_remaining = state.cycles_remaining.saturating_sub(1);
+            state.pomodoros_done += 1;
+            // An interval of 0 never divides a nonzero count, so that turns them off.
+            let long_break_due = state
+                .pomodoros_done
+                .is_multiple_of(config.long_break_interval);
 
-            if state.cycles_remaining > 0 {
-                Notification::new()
-                    .summary("Taiga")
-                    .body("Focus complete! Take a break.")
-                    .show()
-                    .ok();
+            if state.cycles_remaining > 0 && long_break_due {
+                if announce {
+                    notify(
+                        state,
+                        &format!(
+                            "{} pomodoros done! Take a long break.",
+                            state.pomodoros_done
+                        ),
+                    );
+                }
+
+                state.mode = crate::ipc::PomoMode::LongBreak;
+                state.end_time = Some(after(at, config.long_break_duration));
+            } else if state.cycles_remaining > 0 {
+                if announce {
+                    notify(state, "Focus complete! Take a break.");
+                }
 
                 state.mode = crate::ipc::PomoMode::Break;
-                state.end_time = Some(Instant::now() + config.break_duration);
+                state.end_time = Some(after(at, config.break_duration));
             } else {
-                Notification::new()
-                    .summary("Taiga")
-                    .body("All Pomodoros finished! Great work.")
-                    .show()
-                    .ok();
+                if announce {
+                    notify(state, "All Pomodoros finished! Great work.");
+                }
 
                 reset_state(state);
             }
         }
         crate::ipc::PomoMode::Break => {
-            Notification::new()
-                .summary("Taiga")
-                .body("Break over! Back to work.")
-                .show()
-                .ok();
+            if announce {
+                notify(state, "Break over! Back to work.");
+            }
 
             state.mode = crate::ipc::PomoMode::Focus;
-            state.end_time = Some(Instant::now() + config.focus_duration);
+            state.end_time = Some(after(at, config.focus_duration));
+        }
+        crate::ipc::PomoMode::LongBreak => {
+            if announce {
+                notify(state, "Long break over! Feeling fresh? Back to work.");
+            }
+
+            state.mode = crate::ipc::PomoMode::Focus;
+            state.end_time = Some(after(at, config.focus_duration));
         }
         crate::ipc::PomoMode::Idle => {
             reset_state(state);
@@ -245,4 +310,277 @@ fn reset_state(state: &mut TimerState) {
     state.end_time = None;
     state.paused_duration = None;
     state.cycles_remaining = 0;
+    state.pomodoros_done = 0;
+    state.task_id = None;
+    state.task_title = None;
+    state.interval_start = None;
+}
+
+/// Appends the current focus or break, ending at `end`, to the history log if
+/// one is running.
+fn record_interval(state: &TimerState, outcome: Outcome, end: DateTime<Local>) {
+    let (Some(start), Some(config)) = (state.interval_start, &state.config) else {
+        return;
+    };
+    let planned = match state.mode {
+        crate::ipc::PomoMode::Focus => config.focus_duration,
+        crate::ipc::PomoMode::Break => config.break_duration,
+        crate::ipc::PomoMode::LongBreak => config.long_break_duration,
+        crate::ipc::PomoMode::Idle => return,
+    };
+    let remaining = match (state.end_time, state.paused_duration) {
+        (Some(end_time), _) => until(end_time, end),
+        (None, Some(paused)) => paused,
+        (None, None) => Duration::ZERO,
+    };
+    let spent = if outcome == Outcome::Completed {
+        planned
+    } else {
+        planned.saturating_sub(remaining)
+    };
+
+    let session = Session {
+        mode: state.mode,
+        start,
+        end,
+        duration_secs: spent.as_secs(),
+        task_id: state.task_id,
+        outcome,
+    };
+    if let Err(e) = history::append(&state.history_path, &session) {
+        eprintln!("Could not log session: {}", e);
+    }
+}
+
+/// Shows a desktop notification, naming the task the session is for.
+fn notify(state: &TimerState, message: &str) {
+    let body = match (state.task_id, &state.task_title) {
+        (Some(id), Some(title)) => format!("{}\n#{} {}", message, id, title),
+        _ => message.to_string(),
+    };
+    Notification::new().summary("Taiga").body(&body).show().ok();
+}
+
+/// `pomodoro-state.json` in the data directory.
+fn state_path(data_directory: &str) -> PathBuf {
+    Path::new(data_directory).join("pomodoro-state.json")
+}
+
+/// Picks up the timer the last daemon left behind.
+///
+/// If the running interval ran out in the meantime, nobody was there to start
+/// the next one, so it is logged as interrupted and the timer stops.
+fn restore_state(data_directory: &str, now: DateTime<Local>) -> TimerState {
+    let state_path = state_path(data_directory);
+    let mut state = fs::read_to_string(&state_path)
+        .ok()
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .unwrap_or_else(idle_state);
+    state.history_path = history::history_path(data_directory);
+    state.state_path = state_path;
+
+    if state.end_time.is_some_and(|end_time| end_time <= now) {
+        record_interval(&state, Outcome::Interrupted, now);
+        reset_state(&mut state);
+    }
+    save_state(&state);
+    state
+}
+
+fn idle_state() -> TimerState {
+    TimerState {
+        mode: crate::ipc::PomoMode::Idle,
+        end_time: None,
+        cycles_remaining: 0,
+        pomodoros_done: 0,
+        config: None,
+        task_id: None,
+        task_title: None,
+        paused_duration: None,
+        interval_start: None,
+        history_path: PathBuf::new(),
+        state_path: PathBuf::new(),
+    }
+}
+
+fn save_state(state: &TimerState) {
+    let write = || -> Result<(), Box<dyn Error>> {
+        if let Some(dir) = state.state_path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        // Write then rename, so a crash mid-write can't leave half a snapshot.
+        let tmp = state.state_path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_vec(state)?)?;
+        fs::rename(&tmp, &state.state_path)?;
+        Ok(())
+    };
+    if let Err(e) = write() {
+        eprintln!("Could not save timer state: {}", e);
+    }
+}
+
+fn after(at: DateTime<Local>, duration: Duration) -> DateTime<Local> {
+    at + chrono::Duration::from_std(duration).unwrap_or_default()
+}
+
+/// Time left from `now` until `end`, or zero if it has passed.
+fn until(end: DateTime<Local>, now: DateTime<Local>) -> Duration {
+    (end - now).to_std().unwrap_or(Duration::ZERO)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ipc::PomoMode;
+    use crate::task::tests::temp_task_file;
+    use chrono::TimeZone;
+
+    fn data_dir(name: &str) -> String {
+        let path = temp_task_file(name);
+        path.parent().unwrap().to_string_lossy().to_string()
+    }
+
+    /// A focus interval of a 25/5 session with `minutes_left` to go.
+    fn focusing(now: DateTime<Local>, minutes_left: i64, dir: &str) -> TimerState {
+        let end = now + chrono::Duration::minutes(minutes_left);
+        TimerState {
+            mode: PomoMode::Focus,
+            end_time: Some(end),
+            cycles_remaining: 4,
+            config: Some(TimerConfig {
+                focus_duration: Duration::from_secs(25 * 60),
+                break_duration: Duration::from_secs(5 * 60),
+                long_break_duration: Duration::from_secs(15 * 60),
+                long_break_interval: 4,
+            }),
+            interval_start: Some(end - chrono::Duration::minutes(25)),
+            history_path: history::history_path(dir),
+            state_path: state_path(dir),
+            ..idle_state()
+        }
+    }
+
+    /// Runs `state` to the end of its session, naming the mode after each interval.
+    fn run_session(mut state: TimerState) -> Vec<String> {
+        let mut modes = Vec::new();
+        while let Some(end) = state.end_time {
+            handle_timer_transition(&mut state, end, false);
+            modes.push(state.mode.to_string());
+        }
+        modes
+    }
+
+    #[test]
+    fn long_break_follows_every_nth_pomodoro_but_not_the_last() {
+        let dir = data_dir("daemon-long-break");
+        let now = Local.with_ymd_and_hms(2024, 3, 20, 9, 0, 0).unwrap();
+        let session = |cycles: u32, long_break_interval: u32| {
+            let mut state = focusing(now, 25, &dir);
+            state.cycles_remaining = cycles;
+            state.config.as_mut().unwrap().long_break_interval = long_break_interval;
+            run_session(state)
+        };
+
+        assert_eq!(
+            session(5, 4),
+            [
+                "Break",
+                "Focus",
+                "Break",
+                "Focus",
+                "Break",
+                "Focus",
+                "Long break",
+                "Focus",
+                "Idle"
+            ]
+        );
+        // The fourth pomodoro ends the session, so there's no long break to take.
+        assert_eq!(
+            session(4, 4),
+            ["Break", "Focus", "Break", "Focus", "Break", "Focus", "Idle"]
+        );
+        // An interval of 0 turns long breaks off.
+        assert!(!session(5, 0).contains(&"Long break".to_string()));
+
+        // Every interval was logged in order, the long break at its own length.
+        let sessions = history::load(&history::history_path(&dir)).unwrap();
+        let long_break = sessions
+            .iter()
+            .find(|s| matches!(s.mode, PomoMode::LongBreak))
+            .unwrap();
+        assert_eq!(long_break.duration_secs, 15 * 60);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn snapshots_from_before_long_breaks_still_load() {
+        let json = r#"{"mode":"Focus","end_time":"2024-03-20T09:25:00Z","cycles_remaining":2,"config":{"focus_duration":{"secs":1500,"nanos":0},"break_duration":{"secs":300,"nanos":0}},"task_id":null,"task_title":null,"paused_duration":null,"interval_start":"2024-03-20T09:00:00Z"}"#;
+        let dir = data_dir("daemon-old-snapshot");
+        let mut state: TimerState = serde_json::from_str(json).unwrap();
+        state.history_path = history::history_path(&dir);
+        assert_eq!(state.pomodoros_done, 0);
+        assert_eq!(state.config.as_ref().unwrap().long_break_interval, 0);
+        assert_eq!(run_session(state), ["Break", "Focus", "Idle"]);
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn restore_closes_an_interval_that_ran_out_while_down() {
+        let dir = data_dir("daemon-restore-expired");
+        let now = Local.with_ymd_and_hms(2024, 3, 20, 18, 0, 0).unwrap();
+        save_state(&focusing(now, -5 * 60, &dir));
+
+        let restored = restore_state(&dir, now);
+        assert!(matches!(restored.mode, PomoMode::Idle));
+        assert_eq!(restored.end_time, None);
+
+        // Only the interval that was running is logged, and not as a pomodoro.
+        let sessions = history::load(&history::history_path(&dir)).unwrap();
+        assert_eq!(sessions.len(), 1);
+        assert!(matches!(sessions[0].mode, PomoMode::Focus));
+        assert_eq!(sessions[0].outcome, Outcome::Interrupted);
+        assert_eq!(sessions[0].end, now);
+
+        // The stopped timer was saved, so a second restart logs nothing more.
+        assert!(matches!(restore_state(&dir, now).mode, PomoMode::Idle));
+        assert_eq!(
+            history::load(&history::history_path(&dir)).unwrap().len(),
+            1
+        );
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn restore_resumes_running_and_paused_intervals() {
+        let dir = data_dir("daemon-restore-running");
+        let now = Local.with_ymd_and_hms(2024, 3, 20, 18, 0, 0).unwrap();
+        let running = focusing(now, 10, &dir);
+        save_state(&running);
+
+        let restored = restore_state(&dir, now);
+        assert!(matches!(restored.mode, PomoMode::Focus));
+        assert_eq!(restored.end_time, running.end_time);
+        assert_eq!(restored.cycles_remaining, 4);
+
+        // Paused time doesn't run out, however long the daemon was gone.
+        let paused = TimerState {
+            end_time: None,
+            paused_duration: Some(Duration::from_secs(90)),
+            ..focusing(now, 0, &dir)
+        };
+        save_state(&paused);
+        let restored = restore_state(&dir, now + chrono::Duration::days(2));
+        assert!(matches!(restored.mode, PomoMode::Focus));
+        assert_eq!(restored.paused_duration, Some(Duration::from_secs(90)));
+
+        assert!(
+            history::load(&history::history_path(&dir))
+                .unwrap()
+                .is_empty()
+        );
+        fs::remove_dir_all(&dir).ok();
+    }
 }
@@ -0,0 +1,106 @@
+use chrono::{DateTime, Local, NaiveDate, NaiveTime, SecondsFormat, TimeZone, Timelike};
+use chrono_english::{Dialect, parse_date_string};
+
+/// Parses user input like "tomorrow", "next friday" or "friday 9:30".
+///
+/// chrono_english fills in the base time for inputs without one ("tomorrow"), so
+/// we also parse against two base times on the same day: if their times of day
+/// differ the time came from the base, and unless the input was relative to the
+/// clock ("2 hours") the result is trimmed to a date-only midnight.
+pub fn parse_natural(input: &str, now: DateTime<Local>) -> Option<DateTime<Local>> {
+    // "tomorrow at 9:30" reads naturally but chrono_english doesn't know "at".
+    let input = input.trim().to_lowercase().replace(" at ", " ");
+
+    let parsed = parse_date_string(&input, now, Dialect::Us).ok()?;
+    let time_of_day = |base: NaiveTime| {
+        let base = Local
+            .from_local_datetime(&now.date_naive().and_time(base))
+            .earliest()?;
+        parse_date_string(&input, base, Dialect::Us)
+            .ok()
+            .map(|dt| dt.time())
+    };
+    let probe = NaiveTime::from_hms_opt(12, 34, 56)?;
+
+    if time_of_day(NaiveTime::MIN)? == time_of_day(probe)? {
+        return parsed.with_nanosecond(0);
+    }
+
+    let clock_relative = ["now", "hour", "min", "sec"]
+        .iter()
+        .any(|unit| input.contains(unit));
+    if clock_relative {
+        parsed.with_nanosecond(0)
+    } else {
+        start_of_day(parsed.date_naive())
+    }
+}
+
+/// Whether `dt` carries no time of day, i.e. sits exactly on local midnight.
+pub fn is_date_only(dt: &DateTime<Local>) -> bool {
+    dt.time() == NaiveTime::MIN
+}
+
+/// Date-only values are written as `2024-03-20`, timed ones as RFC 3339 to the
+/// second with the local offset.
+pub fn format_md_date(dt: &DateTime<Local>) -> String {
+    if is_date_only(dt) {
+        dt.format("%Y-%m-%d").to_string()
+    } else {
+        dt.to_rfc3339_opts(SecondsFormat::Secs, false)
+    }
+}
+
+/// Reads what `format_md_date` writes. Values with another offset keep their
+/// instant but are converted to local time.
+pub fn parse_md_date(value: &str) -> Option<DateTime<Local>> {
+    match NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        Ok(date) => start_of_day(date),
+        Err(_) => DateTime::parse_from_rfc3339(value)
+            .ok()
+            .map(|dt| dt.with_timezone(&Local)),
+    }
+}
+
+pub fn start_of_day(date: NaiveDate) -> Option<DateTime<Local>> {
+    date.and_hms_opt(0, 0, 0)
+        .and_then(|dt| Local.from_local_datetime(&dt).single())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn natural_dates_only_keep_time_when_given() {
+        let now = Local.with_ymd_and_hms(2024, 3, 20, 14, 12, 5).unwrap();
+
+        let tomorrow = parse_natural("tomorrow", now).unwrap();
+        assert_eq!(
+            tomorrow,
+            Local.with_ymd_and_hms(2024, 3, 21, 0, 0, 0).unwrap()
+        );
+        assert!(is_date_only(&tomorrow));
+
+        let standup = parse_natural("tomorrow at 9:30", now).unwrap();
+        assert_eq!(
+            standup,
+            Local.with_ymd_and_hms(2024, 3, 21, 9, 30, 0).unwrap()
+        );
+        assert!(!is_date_only(&standup));
+    }
+
+    #[test]
+    fn explicit_time_survives_late_evening() {
+        let now = Local.with_ymd_and_hms(2024, 3, 20, 23, 32, 0).unwrap();
+
+        assert_eq!(
+            parse_natural("tomorrow 9:30", now),
+            Some(Local.with_ymd_and_hms(2024, 3, 21, 9, 30, 0).unwrap())
+        );
+        assert_eq!(
+            parse_natural("tomorrow", now),
+            Some(Local.with_ymd_and_hms(2024, 3, 21, 0, 0, 0).unwrap())
+        );
+    }
+}
@@ -0,0 +1,446 @@
+use chrono::{DateTime, Local};
+use regex::Regex;
+use std::collections::HashSet;
+use std::sync::LazyLock;
+
+use crate::dates;
+use crate::task::{Priority, Task, TaskRepository};
+
+/// A parsed task query such as `open and (+work or @office) and not due:none`.
+///
+/// Terms next to each other are and-ed, so plain `+shopping open` keeps working;
+/// `and` binds tighter than `or`, and `not`/`-` negates the next term.
+#[derive(Debug, Clone)]
+pub enum Filter {
+    All,
+    And(Box<Filter>, Box<Filter>),
+    Or(Box<Filter>, Box<Filter>),
+    Not(Box<Filter>),
+    Status(Status),
+    Tag(String),
+    Context(String),
+    /// Case-insensitive substring of the title.
+    Title(String),
+    TitleRegex(Regex),
+    /// `None` matches tasks without a priority.
+    Priority(Option<Priority>),
+    Date(DateField, DateTest),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Status {
+    Open,
+    Done,
+    Overdue,
+    Blocked,
+    Ready,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DateField {
+    Scheduled,
+    Due,
+    Created,
+    Completed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DateTest {
+    /// Same day, or the exact time when one was given.
+    On(DateTime<Local>),
+    Before(DateTime<Local>),
+    After(DateTime<Local>),
+    Missing,
+    Present,
+}
+
+/// What a filter needs to know beyond the task itself.
+pub struct Context {
+    now: DateTime<Local>,
+    blocked: HashSet<u32>,
+    ready: HashSet<u32>,
+}
+
+impl Context {
+    pub fn new(repo: &TaskRepository, now: DateTime<Local>) -> Self {
+        Context {
+            now,
+            blocked: repo.blocked().iter().map(|t| t.id).collect(),
+            ready: repo.ready().iter().map(|t| t.id).collect(),
+        }
+    }
+}
+
+impl Filter {
+    /// Parses a query given as one string, e.g. from `--filter`.
+    pub fn parse(query: &str, now: DateTime<Local>) -> Result<Filter, String> {
+        Parser::new(lex(query)?, now).parse()
+    }
+
+    /// Parses a query given as separate command line words.
+    ///
+    /// The shell has already split these, so a quoted `"due.before:next friday"`
+    /// stays one term while a quoted `"open or +work"` is still split up.
+    pub fn from_args(args: &[&str], now: DateTime<Local>) -> Result<Filter, String> {
+        let mut tokens = Vec::new();
+        for arg in args {
+            if arg.contains(char::is_whitespace)
+                && FIELD_RE.is_match(arg)
+                && !arg.contains(['"', '\'', '(', ')'])
+            {
+                tokens.push(Token::Word(arg.to_string()));
+            } else {
+                tokens.extend(lex(arg)?);
+            }
+        }
+        Parser::new(tokens, now).parse()
+    }
+
+    pub fn matches(&self, task: &Task, cx: &Context) -> bool {
+        match self {
+            Filter::All => true,
+            Filter::And(a, b) => a.matches(task, cx) && b.matches(task, cx),
+            Filter::Or(a, b) => a.matches(task, cx) || b.matches(task, cx),
+            Filter::Not(f) => !f.matches(task, cx),
+            Filter::Status(status) => match status {
+                Status::Open => !task.is_complete,
+                Status::Done => task.is_complete,
+                Status::Overdue => task.is_overdue(cx.now),
+                Status::Blocked => cx.blocked.contains(&task.id),
+                Status::Ready => cx.ready.contains(&task.id),
+            },
+            Filter::Tag(tag) => task.has_tag(tag),
+            Filter::Context(context) => task.has_context(context),
+            Filter::Title(word) => task.title.to_lowercase().contains(word),
+            Filter::TitleRegex(re) => re.is_match(&task.title),
+            Filter::Priority(priority) => task.priority == *priority,
+            Filter::Date(field, test) => {
+                let value = match field {
+                    DateField::Scheduled => task.scheduled,
+                    DateField::Due => task.due,
+                    DateField::Created => task.created_at,
+                    DateField::Completed => task.completed_at,
+                };
+                test.matches(value)
+            }
+        }
+    }
+
+    /// Tasks of `repo` matching the filter, in ID order.
+    pub fn select<'a>(&self, repo: &'a TaskRepository, now: DateTime<Local>) -> Vec<&'a Task> {
+        let cx = Context::new(repo, now);
+        repo.list_all()
+            .into_iter()
+            .filter(|task| self.matches(task, &cx))
+            .collect()
+    }
+}
+
+impl DateTest {
+    fn matches(&self, value: Option<DateTime<Local>>) -> bool {
+        let Some(value) = value else {
+            return *self == DateTest::Missing;
+        };
+        match *self {
+            DateTest::On(day) if dates::is_date_only(&day) => {
+                value.date_naive() == day.date_naive()
+            }
+            DateTest::On(at) => value == at,
+            DateTest::Before(at) => value < at,
+            // "after friday" means from saturday on, not friday 00:01.
+            DateTest::After(day) if dates::is_date_only(&day) => {
+                value.date_naive() > day.date_naive()
+            }
+            DateTest::After(at) => value > at,
+            DateTest::Missing => false,
+            DateTest::Present => true,
+        }
+    }
+}
+
+// `field:value` or `field.op:value`.
+static FIELD_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^[a-z]+(\.[a-z]+)?:").unwrap());
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Open,
+    Close,
+    Word(String),
+}
+
+/// Splits a query into words and parentheses. Quotes group words with spaces, as in
+/// `due.before:"next friday"`, and are dropped; `/regex/` is kept whole.
+fn lex(input: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut word = String::new();
+    let mut quote = None;
+
+    let flush = |word: &mut String, tokens: &mut Vec<Token>| {
+        if !word.is_empty() {
+            tokens.push(Token::Word(std::mem::take(word)));
+        }
+    };
+
+    for c in input.chars() {
+        match (quote, c) {
+            // Regexes keep their slashes so the parser can tell them apart.
+            (Some('/'), '/') => {
+                word.push(c);
+                quote = None;
+            }
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), c) => word.push(c),
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '/') if word.is_empty() => {
+                word.push(c);
+                quote = Some(c);
+            }
+            (None, '(') => {
+                flush(&mut word, &mut tokens);
+                tokens.push(Token::Open);
+            }
+            (None, ')') => {
+                flush(&mut word, &mut tokens);
+                tokens.push(Token::Close);
+            }
+            (None, c) if c.is_whitespace() => flush(&mut word, &mut tokens),
+            (None, c) => word.push(c),
+        }
+    }
+
+    if quote.is_some() {
+        return Err("Unclosed quote or regex in filter".to_string());
+    }
+    flush(&mut word, &mut tokens);
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    now: DateTime<Local>,
+}
+
+impl Parser {
+    fn new(tokens: Vec<Token>, now: DateTime<Local>) -> Self {
+        Parser {
+            tokens,
+            pos: 0,
+            now,
+        }
+    }
+
+    fn parse(mut self) -> Result<Filter, String> {
+        if self.tokens.is_empty() {
+            return Ok(Filter::All);
+        }
+        let filter = self.or()?;
+        match self.tokens.get(self.pos) {
+            None => Ok(filter),
+            Some(Token::Close) => Err("Unmatched ')' in filter".to_string()),
+            Some(token) => Err(format!("Unexpected {:?} in filter", token)),
+        }
+    }
+
+    fn peek_keyword(&self, keyword: &str) -> bool {
+        matches!(self.tokens.get(self.pos), Some(Token::Word(w)) if w.eq_ignore_ascii_case(keyword))
+    }
+
+    fn or(&mut self) -> Result<Filter, String> {
+        let mut left = self.and()?;
+        while self.peek_keyword("or") {
+            self.pos += 1;
+            let right = self.and()?;
+            left = Filter::Or(Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn and(&mut self) -> Result<Filter, String> {
+        let mut left = self.unary()?;
+        loop {
+            if self.peek_keyword("and") {
+                self.pos += 1;
+            } else if self.peek_keyword("or") || self.at_end_of_group() {
+                return Ok(left);
+            }
+            let right = self.unary()?;
+            left = Filter::And(Box::new(left), Box::new(right));
+        }
+    }
+
+    fn at_end_of_group(&self) -> bool {
+        matches!(self.tokens.get(self.pos), None | Some(Token::Close))
+    }
+
+    fn unary(&mut self) -> Result<Filter, String> {
+        let token = self
+            .tokens
+            .get(self.pos)
+            .cloned()
+            .ok_or("Filter ends too early")?;
+        self.pos += 1;
+
+        match token {
+            Token::Open => {
+                let inner = self.or()?;
+                if self.tokens.get(self.pos) != Some(&Token::Close) {
+                    return Err("Missing ')' in filter".to_string());
+                }
+                self.pos += 1;
+                Ok(inner)
+            }
+            Token::Close => Err("Unexpected ')' in filter".to_string()),
+            Token::Word(word) if word.eq_ignore_ascii_case("not") => {
+                Ok(Filter::Not(Box::new(self.unary()?)))
+            }
+            Token::Word(word)
+                if word.eq_ignore_ascii_case("and") || word.eq_ignore_ascii_case("or") =>
+            {
+                Err(format!("'{}' needs something on both sides", word))
+            }
+            Token::Word(word) => match word.strip_prefix('-') {
+                Some(rest) if !rest.is_empty() => Ok(Filter::Not(Box::new(self.term(rest)?))),
+                _ => self.term(&word),
+            },
+        }
+    }
+
+    fn term(&self, word: &str) -> Result<Filter, String> {
+        if let Some(status) = parse_status(word) {
+            return Ok(status.map_or(Filter::All, Filter::Status));
+        }
+        if let Some(tag) = word.strip_prefix('+') {
+            return Ok(Filter::Tag(tag.to_string()));
+        }
+        if let Some(context) = word.strip_prefix('@') {
+            return Ok(Filter::Context(context.to_string()));
+        }
+        if let Some(pattern) = word
+            .strip_prefix('/')
+            .and_then(|w| w.strip_suffix('/'))
+            .filter(|p| !p.is_empty())
+        {
+            return regex(pattern);
+        }
+
+        let Some((key, value)) = word.split_once(':').filter(|_| FIELD_RE.is_match(word)) else {
+            return Ok(Filter::Title(word.to_lowercase()));
+        };
+        let (field, op) = key.split_once('.').unwrap_or((key, "on"));
+
+        let date_field = match field {
+            "scheduled" | "when" => Some(DateField::Scheduled),
+            "due" => Some(DateField::Due),
+            "created" => Some(DateField::Created),
+            "completed" | "done" => Some(DateField::Completed),
+            _ => None,
+        };
+        if let Some(date_field) = date_field {
+            return Ok(Filter::Date(date_field, self.date_test(op, value)?));
+        }
+
+        if op != "on" {
+            return Err(format!("'{}' can't be used with .{}", field, op));
+        }
+        match field {
+            "status" | "is" => match parse_status(value) {
+                Some(status) => Ok(status.map_or(Filter::All, Filter::Status)),
+                None => Err(format!("Unknown status '{}'", value)),
+            },
+            "tag" | "project" => Ok(Filter::Tag(value.trim_start_matches('+').to_string())),
+            "context" => Ok(Filter::Context(value.trim_start_matches('@').to_string())),
+            "title" => Ok(Filter::Title(value.to_lowercase())),
+            "re" | "regex" => regex(value),
+            "priority" => match value {
+                "none" => Ok(Filter::Priority(None)),
+                _ => value.parse::<Priority>().map(|p| Filter::Priority(Some(p))),
+            },
+            _ => Err(format!("Unknown filter field '{}'", field)),
+        }
+    }
+
+    fn date_test(&self, op: &str, value: &str) -> Result<DateTest, String> {
+        match value {
+            "none" => return Ok(DateTest::Missing),
+            "any" => return Ok(DateTest::Present),
+            _ => {}
+        }
+        // `next_friday` saves quoting on the command line.
+        let date = dates::parse_natural(&value.replace('_', " "), self.now)
+            .ok_or_else(|| format!("Could not understand the date '{}'", value))?;
+        match op {
+            "on" => Ok(DateTest::On(date)),
+            "before" => Ok(DateTest::Before(date)),
+            "after" => Ok(DateTest::After(date)),
+            _ => Err(format!(
+                "Unknown date comparison '.{}' (expected before, after or on)",
+                op
+            )),
+        }
+    }
+}
+
+/// `Some(None)` for "all", which matches everything.
+fn parse_status(word: &str) -> Option<Option<Status>> {
+    match word.to_lowercase().as_str() {
+        "all" => Some(None),
+        "open" | "todo" => Some(Some(Status::Open)),
+        "done" => Some(Some(Status::Done)),
+        "overdue" => Some(Some(Status::Overdue)),
+        "blocked" => Some(Some(Status::Blocked)),
+        "ready" => Some(Some(Status::Ready)),
+        _ => None,
+    }
+}
+
+fn regex(pattern: &str) -> Result<Filter, String> {
+    Regex::new(pattern)
+        .map(Filter::TitleRegex)
+        .map_err(|e| format!("Bad regex '{}': {}", pattern, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn task(id: u32, line: &str) -> Task {
+        let mut task = Task::from_md_line(&format!("[ID:{}] - {}", id, line)).unwrap();
+        task.id = id;
+        task
+    }
+
+    #[test]
+    fn queries_combine_status_dates_and_text() {
+        let now = Local.with_ymd_and_hms(2024, 3, 20, 10, 0, 0).unwrap();
+        let mut repo = TaskRepository::new();
+        repo.insert(task(1, "[ ] Buy milk +shopping (Scheduled: 2024-03-20)"));
+        repo.insert(task(2, "[x] Buy bread +shopping (Scheduled: 2024-03-19)"));
+        repo.insert(task(3, "[ ] Write report +work (Due: 2024-03-22)"));
+        repo.insert(task(4, "[ ] Call Mom"));
+
+        let ids = |query: &str| -> Vec<u32> {
+            Filter::parse(query, now)
+                .unwrap()
+                .select(&repo, now)
+                .iter()
+                .map(|t| t.id)
+                .collect()
+        };
+
+        assert_eq!(ids("+shopping open"), vec![1]);
+        assert_eq!(ids("scheduled:today"), vec![1]);
+        assert_eq!(ids("scheduled.before:today"), vec![2]);
+        assert_eq!(ids("due.before:saturday and not done"), vec![3]);
+        assert_eq!(ids("done or +work"), vec![2, 3]);
+        assert_eq!(ids("open and (due:any or -+shopping)"), vec![3, 4]);
+        assert_eq!(ids("/^Buy (milk|eggs)/"), vec![1]);
+        assert_eq!(ids("mom"), vec![4]);
+        assert_eq!(ids(""), vec![1, 2, 3, 4]);
+
+        assert!(Filter::parse("open and", now).is_err());
+        assert!(Filter::parse("(open", now).is_err());
+        assert!(Filter::parse("due.until:friday", now).is_err());
+    }
+}
@@ -0,0 +1,58 @@
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::ipc::PomoMode;
+
+/// One focus or break interval, as logged by the daemon.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Session {
+    pub mode: PomoMode,
+    pub start: DateTime<Local>,
+    pub end: DateTime<Local>,
+    /// Time actually spent in the interval, without pauses.
+    pub duration_secs: u64,
+    pub task_id: Option<u32>,
+    pub outcome: Outcome,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Outcome {
+    /// Ran the full length.
+    Completed,
+    /// Ended early with `taiga pomo stop`.
+    Stopped,
+    /// Cut off by starting a new session, or ran out while the daemon was down.
+    Interrupted,
+}
+
+/// `pomodoro-history.jsonl` in the data directory, one session per line.
+pub fn history_path(data_directory: &str) -> PathBuf {
+    Path::new(data_directory).join("pomodoro-history.jsonl")
+}
+
+pub fn append(path: &Path, session: &Session) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    // One write per line, so concurrent appends can't interleave mid-record.
+    let mut line = serde_json::to_string(session)?;
+    line.push('\n');
+    file.write_all(line.as_bytes())?;
+    Ok(())
+}
+
+/// Every logged session, oldest first. Lines that don't parse are skipped.
+pub fn load(path: &Path) -> Result<Vec<Session>, Box<dyn std::error::Error>> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    Ok(fs::read_to_string(path)?
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect())
+}
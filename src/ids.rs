@@ -0,0 +1,99 @@
+use std::str::FromStr;
+
+/// A single task ID or an inclusive range of them, e.g. `7` or `7-10`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IdRange {
+    start: u32,
+    end: u32,
+}
+
+impl IdRange {
+    /// Whether this names one task rather than a range.
+    pub fn is_single(&self) -> bool {
+        self.start == self.end
+    }
+
+    /// Turns ranges into IDs in the order given, skipping repeats.
+    ///
+    /// A single ID is kept even if it isn't in `known`, so the caller can report it
+    /// missing. A range only yields the `known` IDs inside it: gaps left by removed
+    /// tasks are expected there.
+    pub fn resolve(ranges: &[IdRange], known: &[u32]) -> Vec<u32> {
+        let mut ids = Vec::new();
+        for range in ranges {
+            let found: Vec<u32> = if range.is_single() {
+                vec![range.start]
+            } else {
+                let mut found: Vec<u32> = known
+                    .iter()
+                    .copied()
+                    .filter(|id| (range.start..=range.end).contains(id))
+                    .collect();
+                found.sort_unstable();
+                found
+            };
+            for id in found {
+                if !ids.contains(&id) {
+                    ids.push(id);
+                }
+            }
+        }
+        ids
+    }
+}
+
+impl FromStr for IdRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || format!("'{}' is not a task ID or range like 7-10", s);
+        let s = s.trim().trim_start_matches('#');
+
+        let (start, end) = match s.split_once('-') {
+            Some((start, end)) => (start, end.trim_start_matches('#')),
+            None => (s, s),
+        };
+        let start: u32 = start.trim().parse().map_err(|_| err())?;
+        let end: u32 = end.trim().parse().map_err(|_| err())?;
+
+        if start > end {
+            return Err(format!("Range {}-{} runs backwards", start, end));
+        }
+        Ok(IdRange { start, end })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ranges(input: &[&str]) -> Vec<IdRange> {
+        input.iter().map(|s| s.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn parses_single_ids_and_ranges() {
+        assert_eq!("7-10".parse(), Ok(IdRange { start: 7, end: 10 }));
+        assert_eq!("#3".parse(), Ok(IdRange { start: 3, end: 3 }));
+        assert_eq!("#3-#5".parse(), Ok(IdRange { start: 3, end: 5 }));
+        assert!("10-7".parse::<IdRange>().unwrap_err().contains("backwards"));
+        assert!("seven".parse::<IdRange>().is_err());
+        assert!("3-".parse::<IdRange>().is_err());
+    }
+
+    #[test]
+    fn ranges_skip_gaps_but_single_ids_are_kept() {
+        let known = [1, 2, 4, 5, 9];
+        assert_eq!(
+            IdRange::resolve(&ranges(&["1-5"]), &known),
+            vec![1, 2, 4, 5]
+        );
+        // An unknown single ID survives so the caller can report it.
+        assert_eq!(IdRange::resolve(&ranges(&["3", "9"]), &known), vec![3, 9]);
+        // Given order, no repeats.
+        assert_eq!(
+            IdRange::resolve(&ranges(&["9", "4-9", "1"]), &known),
+            vec![9, 4, 5, 1]
+        );
+    }
+}
@@ -1,19 +1,40 @@
 use directories::BaseDirs;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fmt;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
 
 #[derive(Serialize, Deserialize, Debug, Clone, Copy)]
 pub enum PomoMode {
     Focus,
     Break,
+    LongBreak,
     Idle,
 }
 
+impl fmt::Display for PomoMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            PomoMode::Focus => "Focus",
+            PomoMode::Break => "Break",
+            PomoMode::LongBreak => "Long break",
+            PomoMode::Idle => "Idle",
+        })
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub enum DaemonCommand {
     Start {
-        task_id: u32,
+        task_id: Option<u32>,
+        /// Sent along so the daemon doesn't have to read the task file.
+        task_title: Option<String>,
         focus_len: u64,
         break_len: u64,
+        long_break_len: u64,
+        /// Every how many pomodoros the break is a long one; 0 turns them off.
+        long_break_every: u32,
         cycles: u32,
     },
     Status,
@@ -35,6 +56,7 @@ pub enum DaemonResponse {
         mode: PomoMode,
         cycles_left: u32,
         task_id: Option<u32>,
+        task_title: Option<String>,
     },
     Pong,
 }
@@ -54,3 +76,26 @@ pub fn get_socket_path() -> String {
         path.to_string_lossy().to_string()
     }
 }
+
+/// Sends `message` as one line of JSON. serde_json escapes newlines inside strings,
+/// so the line break always marks the end, however long the message is.
+pub async fn write_message<T: Serialize>(
+    stream: &mut (impl AsyncWrite + Unpin),
+    message: &T,
+) -> Result<(), Box<dyn Error>> {
+    let mut bytes = serde_json::to_vec(message)?;
+    bytes.push(b'\n');
+    stream.write_all(&bytes).await?;
+    Ok(())
+}
+
+/// Reads one message sent with `write_message`, or `None` if the other side hung up.
+pub async fn read_message<T: DeserializeOwned>(
+    stream: &mut (impl AsyncRead + Unpin),
+) -> Result<Option<T>, Box<dyn Error>> {
+    let mut line = String::new();
+    if BufReader::new(stream).read_line(&mut line).await? == 0 {
+        return Ok(None);
+    }
+    Ok(Some(serde_json::from_str(&line)?))
+}
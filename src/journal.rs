@@ -0,0 +1,225 @@
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::task::{Task, TaskRepository, replace_file, sibling_path};
+
+/// How many operations are kept for undo.
+const MAX_ENTRIES: usize = 100;
+
+/// Operation log kept next to the task file (`.taiginator.md.journal`) so changes
+/// can be undone and redone across invocations.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Journal {
+    /// Applied operations, oldest first.
+    done: Vec<Entry>,
+    /// Undone operations, most recently undone last.
+    undone: Vec<Entry>,
+}
+
+/// One command and every task it changed.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Entry {
+    pub at: DateTime<Local>,
+    pub command: String,
+    pub changes: Vec<Change>,
+}
+
+/// A task before and after an operation; `None` means it didn't exist.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Change {
+    pub id: u32,
+    pub before: Option<Task>,
+    pub after: Option<Task>,
+}
+
+impl Change {
+    /// The task as it is now and as it will be once the change is undone or redone.
+    pub fn sides(&self, undo: bool) -> (Option<&Task>, Option<&Task>) {
+        if undo {
+            (self.after.as_ref(), self.before.as_ref())
+        } else {
+            (self.before.as_ref(), self.after.as_ref())
+        }
+    }
+}
+
+impl Journal {
+    pub fn path_for(task_file: &Path) -> PathBuf {
+        sibling_path(task_file, ".", ".journal")
+    }
+
+    pub fn load(task_file: &Path) -> Result<Journal, Box<dyn std::error::Error>> {
+        let path = Journal::path_for(task_file);
+        if !path.exists() {
+            return Ok(Journal::default());
+        }
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    pub fn save(&self, task_file: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        replace_file(&Journal::path_for(task_file), false, |writer| {
+            serde_json::to_writer(writer, self)?;
+            Ok(())
+        })
+    }
+
+    /// Records the difference between `before` and the current state of `repo`.
+    ///
+    /// Returns false when nothing changed. A new operation drops anything that could
+    /// have been redone.
+    pub fn record(
+        &mut self,
+        command: String,
+        before: &HashMap<u32, Task>,
+        repo: &TaskRepository,
+    ) -> bool {
+        let ids: BTreeSet<u32> = before.keys().chain(repo.tasks.keys()).copied().collect();
+        let changes: Vec<Change> = ids
+            .into_iter()
+            .filter_map(|id| {
+                let old = before.get(&id);
+                let new = repo.get(id);
+                let unchanged = match (old, new) {
+                    (Some(a), Some(b)) => same_task(a, b),
+                    (None, None) => true,
+                    _ => false,
+                };
+                (!unchanged).then(|| Change {
+                    id,
+                    before: old.cloned(),
+                    after: new.cloned(),
+                })
+            })
+            .collect();
+
+        if changes.is_empty() {
+            return false;
+        }
+
+        self.done.push(Entry {
+            at: Local::now(),
+            command,
+            changes,
+        });
+        if self.done.len() > MAX_ENTRIES {
+            self.done.remove(0);
+        }
+        self.undone.clear();
+        true
+    }
+
+    /// Reverts the most recent operation on `repo`.
+    pub fn undo(&mut self, repo: &mut TaskRepository) -> Result<Option<Entry>, String> {
+        let Some(entry) = self.done.last() else {
+            return Ok(None);
+        };
+        apply(repo, entry, true)?;
+
+        let entry = self.done.pop().expect("checked above");
+        self.undone.push(entry.clone());
+        Ok(Some(entry))
+    }
+
+    /// Re-applies the most recently undone operation on `repo`.
+    pub fn redo(&mut self, repo: &mut TaskRepository) -> Result<Option<Entry>, String> {
+        let Some(entry) = self.undone.last() else {
+            return Ok(None);
+        };
+        apply(repo, entry, false)?;
+
+        let entry = self.undone.pop().expect("checked above");
+        self.done.push(entry.clone());
+        Ok(Some(entry))
+    }
+}
+
+/// Moves every task in `entry` from one side of the change to the other.
+///
+/// Refuses to touch anything if a task no longer looks like the operation left it
+/// (e.g. it was edited by hand since), so we never overwrite newer changes.
+fn apply(repo: &mut TaskRepository, entry: &Entry, undo: bool) -> Result<(), String> {
+    for change in &entry.changes {
+        let (expected, _) = change.sides(undo);
+        let current = repo.get(change.id);
+        let matches = match (expected, current) {
+            (Some(a), Some(b)) => same_task(a, b),
+            (None, None) => true,
+            _ => false,
+        };
+        if !matches {
+            return Err(format!(
+                "Task #{} changed since '{}', not touching it.",
+                change.id, entry.command
+            ));
+        }
+    }
+
+    // Remove first so inserts of reparented subtasks aren't undone by the removal.
+    for change in &entry.changes {
+        if change.sides(undo).1.is_none() {
+            repo.remove(change.id);
+        }
+    }
+    for change in &entry.changes {
+        if let (_, Some(task)) = change.sides(undo) {
+            repo.insert(task.clone());
+        }
+    }
+    Ok(())
+}
+
+/// Compares tasks the way they are stored, ignoring precision the file doesn't keep.
+fn same_task(a: &Task, b: &Task) -> bool {
+    a.to_md_line() == b.to_md_line() && a.parent == b.parent
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::task::tests::temp_task_file;
+    use crate::trash;
+
+    #[test]
+    fn undo_and_redo_a_removal_with_subtasks() {
+        let path = temp_task_file("journal");
+        let now = Local::now();
+        let mut repo = TaskRepository::new();
+        let mut journal = Journal::default();
+
+        let before = repo.tasks.clone();
+        let release = repo.add(Task::new("Release".to_string()));
+        let tag = repo.add(Task::new("Tag".to_string()).parent(Some(release)));
+        assert!(journal.record("add".to_string(), &before, &repo));
+
+        // Removing the parent moves its subtask up a level.
+        let before = repo.tasks.clone();
+        trash::trash_tasks(&mut repo, &[release], &path, now).unwrap();
+        assert_eq!(repo.get(tag).unwrap().parent, None);
+        assert!(journal.record("remove".to_string(), &before, &repo));
+        journal.save(&path).unwrap();
+
+        let mut journal = Journal::load(&path).unwrap();
+        let entry = journal.undo(&mut repo).unwrap().unwrap();
+        trash::follow(&entry, true, &path, now).unwrap();
+        assert!(repo.get(release).is_some());
+        assert_eq!(repo.get(tag).unwrap().parent, Some(release));
+        assert!(trash::load(&path).unwrap().get(release).is_none());
+
+        let entry = journal.redo(&mut repo).unwrap().unwrap();
+        trash::follow(&entry, false, &path, now).unwrap();
+        assert!(repo.get(release).is_none());
+        assert_eq!(repo.get(tag).unwrap().parent, None);
+        assert!(trash::load(&path).unwrap().get(release).is_some());
+
+        // A task changed since the removal is left alone, and so is the rest.
+        repo.get_mut(tag).unwrap().set_title("Tag v1".to_string());
+        assert!(journal.undo(&mut repo).is_err());
+        assert_eq!(repo.get(tag).unwrap().title, "Tag v1");
+        assert!(repo.get(release).is_none());
+
+        fs::remove_dir_all(path.parent().unwrap()).ok();
+    }
+}
@@ -1,16 +1,30 @@
+use std::collections::HashSet;
 use std::path::PathBuf;
 
-use chrono::Local;
-use chrono_english::{Dialect, parse_date_string};
+use chrono::{DateTime, Local};
+use clap::ArgMatches;
 
-use crate::task::TaskRepository;
+use crate::filter::Filter;
+use crate::ids::IdRange;
+use crate::journal::{Entry, Journal};
+use crate::recurrence::Recurrence;
+use crate::task::{Priority, Task, TaskRepository};
 
+mod archive;
 mod cli;
 mod client;
 mod config;
 mod daemon;
+mod dates;
+mod filter;
+mod history;
+mod ids;
 mod ipc;
+mod journal;
+mod recurrence;
+mod stats;
 mod task;
+mod trash;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -24,89 +38,571 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let cfg: config::Config = confy::load("taiga", None)?;
     let mut tasks_file_path = PathBuf::from(&cfg.data_directory);
     tasks_file_path.push(&cfg.task_filename);
-    let mut repo = TaskRepository::load_from_file(&tasks_file_path)?;
+    // Saves are atomic, so commands that only read can skip the lock and keep
+    // working while another taiga holds it.
+    let mut lock = if changes_files(&matches) {
+        Some(TaskRepository::lock(&tasks_file_path)?)
+    } else {
+        None
+    };
+    let mut repo = load_repo(&tasks_file_path)?;
+    if lock.is_some()
+        && let Some(days) = cfg.trash_retention_days
+    {
+        trash::purge(
+            &tasks_file_path,
+            Some(Local::now() - chrono::Duration::days(days as i64)),
+        )?;
+    }
+    // Only commands that record or replay changes read the journal, so a damaged
+    // one can't get in the way of listing tasks.
+    let mut journal = match matches.subcommand_name() {
+        Some(name) if JOURNALED.contains(&name) || matches!(name, "undo" | "redo") => {
+            Journal::load(&tasks_file_path)?
+        }
+        _ => Journal::default(),
+    };
+    let mut before = repo.tasks.clone();
 
     match matches.subcommand() {
         Some(("add", sub_matches)) => {
-            let title = sub_matches.get_one::<String>("TITLE").expect("required");
-
-            println!("it hits the ADD");
-            let parsed_time = match sub_matches.subcommand() {
-                Some(("when", when_matches)) => {
-                    println!("it hits the when");
-                    let date_str = when_matches
-                        .get_one::<String>("SCHEDULED")
-                        .expect("required");
-                    parse_date_string(date_str, Local::now(), Dialect::Us).ok()
-                }
-                _ => None,
+            let title = sub_matches
+                .get_many::<String>("TITLE")
+                .expect("required")
+                .map(|s| s.as_str())
+                .collect::<Vec<_>>()
+                .join(" ");
+            let priority = sub_matches.get_one::<Priority>("PRIORITY").copied();
+            let recurrence = sub_matches.get_one::<Recurrence>("REPEAT").copied();
+            let parent = sub_matches.get_one::<u32>("PARENT").copied();
+            let depends_on: Vec<u32> = sub_matches
+                .get_many::<u32>("DEPENDS_ON")
+                .map(|ids| ids.copied().collect())
+                .unwrap_or_default();
+
+            if let Some(missing) = depends_on.iter().find(|id| repo.get(**id).is_none()) {
+                println!("Error: Task #{} not found.", missing);
+                return Ok(());
+            }
+
+            if let Some(parent_id) = parent
+                && repo.get(parent_id).is_none()
+            {
+                println!("Error: Parent task #{} not found.", parent_id);
+                return Ok(());
+            }
+
+            let Some((parsed_time, parsed_due)) = parse_date_clauses(sub_matches) else {
+                return Ok(());
             };
 
             println!("Adding {}", title);
-            repo.add(title.to_string(), parsed_time);
+            let id = repo.add(
+                Task::new(title)
+                    .scheduled(parsed_time)
+                    .due(parsed_due)
+                    .priority(priority)
+                    .recurrence(recurrence)
+                    .parent(parent)
+                    .depends_on(depends_on),
+            );
             repo.save_to_file(&tasks_file_path)?;
-            println!("Task saved.");
+            if let Some(task) = repo.get(id) {
+                println!("Task saved: {}", task.to_md_line());
+            }
         }
         Some(("list", sub_matches)) => {
-            let state = sub_matches
-                .get_one::<String>("STATE")
-                .map(|s| s.as_str())
-                .unwrap_or("all");
-            println!("Listing tasks [{}]", state);
+            let args: Vec<&str> = sub_matches
+                .get_many::<String>("STATE")
+                .map(|vals| vals.map(|s| s.as_str()).collect())
+                .unwrap_or_default();
 
-            let tasks = repo.list_all();
+            if args.is_empty() {
+                println!("Listing tasks [all]");
+            } else {
+                println!("Listing tasks [{}]", args.join(" "));
+            }
 
-            if tasks.is_empty() {
-                println!("No tasks found.");
+            let sort = if sub_matches.get_flag("OLDEST") {
+                "created"
             } else {
-                for task in tasks {
-                    let should_show = match state {
-                        "open" => !task.is_complete,
-                        "done" => task.is_complete,
-                        _ => true,
-                    };
+                sub_matches
+                    .get_one::<String>("SORT")
+                    .map_or("priority", |s| s.as_str())
+            };
+            let options = ListOptions {
+                sort: sort.to_string(),
+                descending: sub_matches.get_flag("DESC"),
+                group_by: sub_matches.get_one::<String>("GROUP_BY").cloned(),
+                older_than: sub_matches
+                    .get_one::<u32>("OLDER_THAN")
+                    .map(|days| chrono::Duration::days(*days as i64)),
+            };
 
-                    if should_show {
-                        println!("{}", task.to_md_line());
-                    }
+            let filter = match Filter::from_args(&args, Local::now()) {
+                Ok(filter) => filter,
+                Err(e) => {
+                    println!("Error: {}", e);
+                    return Ok(());
+                }
+            };
+
+            if sub_matches.get_flag("ARCHIVED") {
+                let files = archive::archive_files(&tasks_file_path)?;
+                if files.is_empty() {
+                    println!("No archived tasks found.");
+                }
+                for file in files {
+                    let archived = TaskRepository::load_from_file(&file)?;
+                    println!("== {} ==", file.display());
+                    print_matching(&archived, &filter, &options);
                 }
+            } else {
+                print_matching(&repo, &filter, &options);
+            }
+        }
+
+        Some(("archive", sub_matches)) => {
+            let cutoff = sub_matches
+                .get_one::<u32>("OLDER_THAN")
+                .map(|days| Local::now() - chrono::Duration::days(*days as i64));
+
+            let ids: Vec<u32> = repo
+                .list_all()
+                .into_iter()
+                .filter(|task| archive::is_archivable(task, cutoff))
+                .map(|task| task.id)
+                .collect();
+
+            if ids.is_empty() {
+                println!("Nothing to archive.");
+            } else {
+                let path = archive::archive_tasks(&mut repo, &ids, &tasks_file_path)?;
+                repo.save_to_file(&tasks_file_path)?;
+                println!("Archived {} task(s) to {}", ids.len(), path.display());
             }
         }
 
         Some(("check", sub_matches)) => {
-            let id = *sub_matches.get_one::<u32>("ID").expect("required");
+            let Some(ids) = target_ids(&repo, sub_matches) else {
+                return Ok(());
+            };
+            if ids.is_empty() {
+                println!("No tasks matched.");
+                return Ok(());
+            }
+
+            // Checking a single task again reopens it. In bulk that would flip
+            // whatever was already done, so reopening has to be asked for.
+            let reopen = sub_matches.get_flag("REOPEN");
+            let single = !sub_matches.contains_id("FILTER")
+                && matches!(
+                    sub_matches
+                        .get_many::<IdRange>("ID")
+                        .map(|ranges| ranges.collect::<Vec<_>>())
+                        .as_deref(),
+                    Some([range]) if range.is_single()
+                );
+
+            let mut changed = false;
+            for id in ids {
+                let Some(task) = repo.get_mut(id) else {
+                    println!("Error: Task #{} not found.", id);
+                    continue;
+                };
 
-            match repo.get_mut(id) {
-                Some(task) => {
+                if reopen || (single && task.is_complete) {
                     if task.is_complete {
-                        task.is_complete = false;
+                        task.reopen();
+                        changed = true;
                         println!("Marked task #{} as open: {}", task.id, task.title);
                     } else {
-                        task.is_complete = true;
-                        println!("Marked task #{} as done: {}", task.id, task.title);
+                        println!("Task #{} is already open: {}", task.id, task.title);
                     }
+                    continue;
+                }
+                if task.is_complete {
+                    println!("Task #{} is already done: {}", task.id, task.title);
+                    continue;
+                }
+                changed = true;
 
-                    repo.save_to_file(&tasks_file_path)?;
+                task.complete(Local::now());
+                println!("Marked task #{} as done: {}", task.id, task.title);
+
+                let open = repo.open_subtasks(id);
+                if open > 0 {
+                    println!("Note: {} subtask(s) still open.", open);
+                }
+
+                if let Some(task) = repo.get(id) {
+                    let waiting_on = repo.open_dependencies(task);
+                    if !waiting_on.is_empty() {
+                        println!(
+                            "Warning: task #{} depends on {} which {} still open.",
+                            id,
+                            format_ids(&waiting_on),
+                            if waiting_on.len() == 1 { "is" } else { "are" }
+                        );
+                    }
+                }
+
+                if let Some(next_id) = repo.schedule_next_occurrence(id)
+                    && let Some(next) = repo.get(next_id)
+                {
+                    println!("Next occurrence: {}", next.to_md_line());
                 }
-                None => {
+            }
+
+            if changed {
+                repo.save_to_file(&tasks_file_path)?;
+            }
+        }
+
+        Some(("edit", sub_matches)) => {
+            let Some(ids) = target_ids(&repo, sub_matches) else {
+                return Ok(());
+            };
+            let title = sub_matches
+                .get_many::<String>("TITLE")
+                .map(|vals| vals.map(|s| s.as_str()).collect::<Vec<_>>().join(" "))
+                .or_else(|| sub_matches.get_one::<String>("NEW_TITLE").cloned());
+            let unschedule = sub_matches.get_flag("UNSCHEDULE");
+            let recurrence = sub_matches.get_one::<Recurrence>("REPEAT").copied();
+            let no_repeat = sub_matches.get_flag("NO_REPEAT");
+            let no_due = sub_matches.get_flag("NO_DUE");
+            let Some((scheduled, due)) = parse_date_clauses(sub_matches) else {
+                return Ok(());
+            };
+
+            let in_editor = title.is_none()
+                && !unschedule
+                && recurrence.is_none()
+                && !no_repeat
+                && !no_due
+                && scheduled.is_none()
+                && due.is_none();
+
+            if ids.is_empty() {
+                println!("No tasks matched.");
+                return Ok(());
+            }
+            if in_editor && ids.len() > 1 {
+                println!("Error: Only one task at a time can be edited in $EDITOR.");
+                return Ok(());
+            }
+
+            let mut edited = None;
+            if in_editor {
+                let id = ids[0];
+                let Some(original) = repo.get(id).cloned() else {
                     println!("Error: Task #{} not found.", id);
+                    return Ok(());
+                };
+
+                // Don't hold up other taiga commands while the editor is open.
+                drop(lock.take());
+                let edit = edit_in_editor(&original)?;
+                lock.replace(TaskRepository::lock(&tasks_file_path)?);
+
+                repo = load_repo(&tasks_file_path)?;
+                journal = Journal::load(&tasks_file_path)?;
+                before = repo.tasks.clone();
+                let unchanged = repo.get(id).is_some_and(|task| {
+                    task.to_md_line() == original.to_md_line() && task.parent == original.parent
+                });
+                if !unchanged {
+                    println!(
+                        "Error: Task #{} was changed by another taiga command while you were editing it. Your edit was not saved.",
+                        id
+                    );
+                    return Ok(());
+                }
+                edited = Some(edit);
+            }
+
+            let mut changed = false;
+            for id in ids {
+                let Some(task) = repo.get_mut(id) else {
+                    println!("Error: Task #{} not found.", id);
+                    continue;
+                };
+                changed = true;
+
+                if let Some(edited) = edited.take() {
+                    task.set_title(edited.title);
+                    if edited.is_complete && !task.is_complete {
+                        task.complete(edited.completed_at.unwrap_or_else(Local::now));
+                    } else if !edited.is_complete {
+                        task.reopen();
+                    } else {
+                        task.completed_at = edited.completed_at.or(task.completed_at);
+                    }
+                    task.scheduled = edited.scheduled;
+                    task.due = edited.due;
+                    task.priority = edited.priority;
+                    task.recurrence = edited.recurrence;
+
+                    // New links go through add_dependency so cycles are still refused.
+                    task.depends_on.retain(|on| edited.depends_on.contains(on));
+                    let new_links: Vec<u32> = edited
+                        .depends_on
+                        .iter()
+                        .copied()
+                        .filter(|on| !task.depends_on.contains(on))
+                        .collect();
+                    for on in new_links {
+                        if let Err(e) = repo.add_dependency(id, on) {
+                            println!("Error: {}", e);
+                        }
+                    }
+                } else {
+                    if let Some(title) = &title {
+                        task.set_title(title.clone());
+                    }
+                    if unschedule {
+                        task.scheduled = None;
+                    }
+                    if no_repeat {
+                        task.recurrence = None;
+                    }
+                    if no_due {
+                        task.due = None;
+                    }
+                    if recurrence.is_some() {
+                        task.recurrence = recurrence;
+                    }
+                    if scheduled.is_some() {
+                        task.scheduled = scheduled;
+                    }
+                    if due.is_some() {
+                        task.due = due;
+                    }
                 }
+
+                if let Some(task) = repo.get(id) {
+                    println!("Updated: {}", task.to_md_line());
+                }
+            }
+
+            if changed {
+                repo.save_to_file(&tasks_file_path)?;
             }
         }
 
-        Some(("remove", sub_matches)) => {
+        Some(("depend", sub_matches)) => {
             let id = *sub_matches.get_one::<u32>("ID").expect("required");
-            println!("Removing {}", id);
+            let ranges: Vec<IdRange> = sub_matches
+                .get_many::<IdRange>("ON")
+                .expect("required")
+                .copied()
+                .collect();
+            let known: Vec<u32> = repo.tasks.keys().copied().collect();
+            let on = IdRange::resolve(&ranges, &known);
+
+            if sub_matches.get_flag("REMOVE") {
+                match repo.get_mut(id) {
+                    Some(task) => {
+                        task.depends_on.retain(|dep| !on.contains(dep));
+                        println!("Task #{} no longer depends on {}.", id, format_ids(&on));
+                    }
+                    None => {
+                        println!("Error: Task #{} not found.", id);
+                        return Ok(());
+                    }
+                }
+            } else {
+                for dep in &on {
+                    if let Err(e) = repo.add_dependency(id, *dep) {
+                        println!("Error: {}", e);
+                        return Ok(());
+                    }
+                }
+                println!("Task #{} now depends on {}.", id, format_ids(&on));
+            }
+
+            repo.save_to_file(&tasks_file_path)?;
+        }
+
+        Some(("priority", sub_matches)) => {
+            let mut values: Vec<&String> = sub_matches
+                .get_many::<String>("ID_LEVEL")
+                .expect("required")
+                .collect();
+            let level = values.pop().expect("required");
+            let priority = match level.to_lowercase().as_str() {
+                "none" | "clear" => None,
+                _ => match level.parse::<Priority>() {
+                    Ok(priority) => Some(priority),
+                    Err(e) => {
+                        println!("Error: {}", e);
+                        return Ok(());
+                    }
+                },
+            };
+
+            let filter = sub_matches.get_one::<String>("FILTER");
+            let ids = match (filter, values.is_empty()) {
+                (Some(filter), true) => match filter_ids(&repo, filter) {
+                    Some(ids) => ids,
+                    None => return Ok(()),
+                },
+                (None, false) => {
+                    let ranges = match values
+                        .iter()
+                        .flat_map(|value| value.split(','))
+                        .map(|value| value.parse::<IdRange>())
+                        .collect::<Result<Vec<_>, _>>()
+                    {
+                        Ok(ranges) => ranges,
+                        Err(e) => {
+                            println!("Error: {}", e);
+                            return Ok(());
+                        }
+                    };
+                    let known: Vec<u32> = repo.tasks.keys().copied().collect();
+                    IdRange::resolve(&ranges, &known)
+                }
+                (Some(_), false) => {
+                    println!("Error: Give either task IDs or --filter, not both.");
+                    return Ok(());
+                }
+                (None, true) => {
+                    println!("Error: Give the task IDs to reprioritize or a --filter.");
+                    return Ok(());
+                }
+            };
+
+            if ids.is_empty() {
+                println!("No tasks matched.");
+                return Ok(());
+            }
+
+            let mut changed = false;
+            for id in ids {
+                match repo.get_mut(id) {
+                    Some(task) => {
+                        task.priority = priority;
+                        changed = true;
+                        match priority {
+                            Some(p) => println!("Set priority of task #{} to {}.", task.id, p),
+                            None => println!("Cleared priority of task #{}.", task.id),
+                        }
+                    }
+                    None => {
+                        println!("Error: Task #{} not found.", id);
+                    }
+                }
+            }
+
+            if changed {
+                repo.save_to_file(&tasks_file_path)?;
+            }
+        }
+
+        Some(("remove", sub_matches)) => {
+            let Some(ids) = target_ids(&repo, sub_matches) else {
+                return Ok(());
+            };
+            if ids.is_empty() {
+                println!("No tasks matched.");
+                return Ok(());
+            }
+            println!("Removing {}", format_ids(&ids));
 
-            match repo.remove(id) {
-                Some(removed_task) => {
+            let (found, missing): (Vec<u32>, Vec<u32>) =
+                ids.into_iter().partition(|id| repo.get(*id).is_some());
+            for id in missing {
+                println!("Error: Task with ID {} not found.", id);
+            }
+
+            if !found.is_empty() {
+                for removed_task in
+                    trash::trash_tasks(&mut repo, &found, &tasks_file_path, Local::now())?
+                {
                     println!("Removed: {}", removed_task.title);
+                }
+                repo.save_to_file(&tasks_file_path)?;
+            }
+        }
+
+        Some(("trash", sub_matches)) => match sub_matches.subcommand() {
+            Some(("list", _)) => {
+                let trashed = trash::load(&tasks_file_path)?;
+                let mut tasks = trashed.list_all();
+                if tasks.is_empty() {
+                    println!("Trash is empty.");
+                }
+                tasks.sort_by_key(|t| t.deleted_at);
+                for task in tasks {
+                    println!("{}", task.to_md_line());
+                }
+            }
+            Some(("restore", restore_matches)) => {
+                let ranges: Vec<IdRange> = restore_matches
+                    .get_many::<IdRange>("ID")
+                    .expect("required")
+                    .copied()
+                    .collect();
+                let known: Vec<u32> = trash::load(&tasks_file_path)?.tasks.into_keys().collect();
+                let ids = IdRange::resolve(&ranges, &known);
+                if ids.is_empty() {
+                    println!("No tasks matched.");
+                }
+
+                let mut restored = false;
+                for id in ids {
+                    match trash::restore(&mut repo, id, &tasks_file_path) {
+                        Ok(Some(task)) => {
+                            restored = true;
+                            println!("Restored: {}", task.to_md_line());
+                        }
+                        Ok(None) => println!("Error: Task #{} is not in the trash.", id),
+                        Err(e) => println!("Error: {}", e),
+                    }
+                }
+                if restored {
                     repo.save_to_file(&tasks_file_path)?;
                 }
-                None => {
-                    println!("Error: Task with ID {} not found.", id);
+            }
+            Some(("empty", _)) => {
+                let purged = trash::purge(&tasks_file_path, None)?;
+                println!("Deleted {} task(s) for good.", purged);
+            }
+            _ => unreachable!(),
+        },
+
+        Some((direction @ ("undo" | "redo"), sub_matches)) => {
+            let steps = *sub_matches.get_one::<u32>("STEPS").expect("defaulted");
+            let mut applied = 0;
+
+            for _ in 0..steps {
+                let result = if direction == "undo" {
+                    journal.undo(&mut repo)
+                } else {
+                    journal.redo(&mut repo)
+                };
+                match result {
+                    Ok(Some(entry)) => {
+                        trash::follow(&entry, direction == "undo", &tasks_file_path, Local::now())?;
+                        print_entry(&entry, direction == "undo");
+                        applied += 1;
+                    }
+                    Ok(None) => {
+                        println!("Nothing to {}.", direction);
+                        break;
+                    }
+                    Err(e) => {
+                        println!("Error: {}", e);
+                        break;
+                    }
                 }
             }
+
+            if applied > 0 {
+                repo.save_to_file(&tasks_file_path)?;
+                journal.save(&tasks_file_path)?;
+            }
         }
 
         Some(("pomo", sub_matches)) => {
@@ -119,34 +615,64 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                         start_matches.get_one::<String>("BREAK").unwrap().parse()?;
                     let cycles_input: u32 =
                         start_matches.get_one::<String>("CYCLES").unwrap().parse()?;
+                    let long_break_input = *start_matches.get_one::<u64>("LONG_BREAK").unwrap();
+                    let long_break_every =
+                        *start_matches.get_one::<u32>("LONG_BREAK_EVERY").unwrap();
+                    let task_id = start_matches.get_one::<u32>("TASK").copied();
+                    let task_title = match task_id.map(|id| (id, repo.get(id))) {
+                        Some((_, Some(task))) => Some(task.title.clone()),
+                        Some((id, None)) => {
+                            println!("Error: Task #{} not found.", id);
+                            return Ok(());
+                        }
+                        None => None,
+                    };
                     // IPC Call
                     let resp = client::send_command(ipc::DaemonCommand::Start {
-                        task_id: 0, // Pass real ID if you have it
+                        task_id,
+                        task_title,
                         focus_len: focus_input,
                         break_len: break_input,
+                        long_break_len: long_break_input,
+                        long_break_every,
                         cycles: cycles_input,
                     })
                     .await?;
                     println!("{:?}", resp);
                 }
                 // TODO: Prettify all Pomo outputs
-                Some(("status", status_matches)) => {
+                Some(("status", _)) => {
                     let resp = client::send_command(ipc::DaemonCommand::Status).await?;
-                    println!("{:?}", resp);
+                    print_pomo_status(&resp);
                 }
-                Some(("stop", stop_matches)) => {
+                Some(("stop", _)) => {
                     client::send_command(ipc::DaemonCommand::Stop).await?;
                     println!("Timer stopped.");
                 }
-                Some(("pause", pause_matches)) => {
+                Some(("pause", _)) => {
                     let resp = client::send_command(ipc::DaemonCommand::Pause).await?;
                     println!("{:?}", resp);
                 }
-                Some(("resume", resume_matches)) => {
+                Some(("resume", _)) => {
                     let resp = client::send_command(ipc::DaemonCommand::Resume).await?;
                     println!("{:?}", resp);
                 }
-                Some(("kill", kill_matches)) => {
+                Some(("stats", stats_matches)) => {
+                    let sessions = history::load(&history::history_path(&cfg.data_directory))?;
+                    if sessions.is_empty() {
+                        println!("No pomodoros logged yet.");
+                        return Ok(());
+                    }
+
+                    let now = Local::now();
+                    print_pomo_stats(&stats::compute(&sessions, now), &repo);
+                    if stats_matches.get_flag("CHART") {
+                        let days = *stats_matches.get_one::<u32>("DAYS").expect("defaulted");
+                        println!();
+                        print_focus_chart(&stats::daily_focus(&sessions, now, days));
+                    }
+                }
+                Some(("kill", _)) => {
                     let resp = client::send_command(ipc::DaemonCommand::Kill).await?;
                     println!("{:?}", resp);
                 }
@@ -158,5 +684,583 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         _ => unreachable!(),
     };
 
+    // Early returns above leave the file untouched, so anything that changed here was saved.
+    if let Some((name, _)) = matches.subcommand()
+        && JOURNALED.contains(&name)
+    {
+        let command = std::env::args().skip(1).collect::<Vec<_>>().join(" ");
+        if journal.record(command, &before, &repo) {
+            journal.save(&tasks_file_path)?;
+        }
+    }
+
     Ok(())
 }
+
+/// Loads the task file, bringing files from before the ID counter up to date.
+fn load_repo(tasks_file_path: &PathBuf) -> Result<TaskRepository, Box<dyn std::error::Error>> {
+    let mut repo = TaskRepository::load_from_file(tasks_file_path)?;
+    if !repo.has_id_counter {
+        // Files from before the counter was stored may have forgotten removed IDs,
+        // so skip past anything still sitting in an archive or the trash.
+        for file in archive::archive_files(tasks_file_path)? {
+            repo.reserve_ids(&TaskRepository::load_from_file(&file)?);
+        }
+        repo.reserve_ids(&trash::load(tasks_file_path)?);
+    }
+    Ok(repo)
+}
+
+/// Whether the command can write the task file, its archives or trash, and so
+/// needs the lock.
+fn changes_files(matches: &ArgMatches) -> bool {
+    match matches.subcommand() {
+        Some(("list" | "pomo", _)) => false,
+        Some(("trash", sub_matches)) => sub_matches.subcommand_name() != Some("list"),
+        _ => true,
+    }
+}
+
+/// Commands whose changes can be undone.
+const JOURNALED: [&str; 6] = ["add", "check", "edit", "depend", "priority", "remove"];
+
+/// Shows what undoing or redoing `entry` did, diff style.
+fn print_entry(entry: &Entry, undone: bool) {
+    println!(
+        "{} '{}' from {}:",
+        if undone { "Undid" } else { "Redid" },
+        entry.command,
+        entry.at.format("%Y-%m-%d %H:%M")
+    );
+    for change in &entry.changes {
+        let (from, to) = change.sides(undone);
+        // Subtasks that only moved to another parent look the same on both sides.
+        if let (Some(a), Some(b)) = (from, to)
+            && a.to_md_line() == b.to_md_line()
+        {
+            continue;
+        }
+        if let Some(task) = from {
+            println!("  - {}", task.to_md_line());
+        }
+        if let Some(task) = to {
+            println!("  + {}", task.to_md_line());
+        }
+    }
+}
+
+fn print_pomo_status(resp: &ipc::DaemonResponse) {
+    let ipc::DaemonResponse::Status {
+        remaining_secs,
+        is_running,
+        mode,
+        cycles_left,
+        task_id,
+        task_title,
+    } = resp
+    else {
+        println!("{:?}", resp);
+        return;
+    };
+
+    if matches!(mode, ipc::PomoMode::Idle) {
+        println!("No pomodoro running.");
+        return;
+    }
+    println!(
+        "{}: {:02}:{:02} left{} ({} cycle(s) to go)",
+        mode,
+        remaining_secs / 60,
+        remaining_secs % 60,
+        if *is_running { "" } else { ", paused" },
+        cycles_left
+    );
+    if let (Some(id), Some(title)) = (task_id, task_title) {
+        println!("Working on #{} {}", id, title);
+    }
+}
+
+fn print_pomo_stats(stats: &stats::Stats, repo: &TaskRepository) {
+    println!("Focus time");
+    for (label, total) in [
+        ("Today", stats.today),
+        ("This week", stats.this_week),
+        ("This month", stats.this_month),
+        ("All time", stats.all_time),
+    ] {
+        println!(
+            "  {:<12}{:>8}  {:>3} pomodoro(s)",
+            label,
+            format_focus(total.focus_secs),
+            total.pomodoros
+        );
+    }
+
+    println!();
+    println!(
+        "Streak: {} day(s), longest {}",
+        stats.current_streak, stats.longest_streak
+    );
+    if let Some(rate) = stats.completion_rate {
+        println!("Completion rate: {:.0}%", rate * 100.0);
+    }
+
+    println!();
+    println!("  {:<40}{:>8}  {:>9}", "Task", "Focus", "Pomodoros");
+    for (id, total) in &stats.per_task {
+        let name = match id {
+            Some(id) => match repo.get(*id) {
+                Some(task) => format!("#{} {}", id, task.title),
+                None => format!("#{} (no longer in the list)", id),
+            },
+            None => "(no task)".to_string(),
+        };
+        println!(
+            "  {:<40}{:>8}  {:>9}",
+            truncate(&name, 40),
+            format_focus(total.focus_secs),
+            total.pomodoros
+        );
+    }
+}
+
+/// One `#` bar per day, scaled to the busiest day.
+fn print_focus_chart(days: &[(chrono::NaiveDate, u64)]) {
+    const WIDTH: u64 = 40;
+    let max = days.iter().map(|(_, secs)| *secs).max().unwrap_or(0).max(1);
+    for (day, secs) in days {
+        let bar = "#".repeat((secs * WIDTH).div_ceil(max) as usize);
+        println!(
+            "{} | {:<width$} {}",
+            day.format("%a %m-%d"),
+            bar,
+            format_focus(*secs),
+            width = WIDTH as usize
+        );
+    }
+}
+
+/// Focus time like "1h 05m" or "25m".
+fn format_focus(secs: u64) -> String {
+    let minutes = secs / 60;
+    if minutes >= 60 {
+        format!("{}h {:02}m", minutes / 60, minutes % 60)
+    } else {
+        format!("{}m", minutes)
+    }
+}
+
+fn truncate(text: &str, max: usize) -> String {
+    if text.chars().count() <= max {
+        text.to_string()
+    } else {
+        let cut: String = text.chars().take(max - 1).collect();
+        format!("{}…", cut)
+    }
+}
+
+/// Finds the `when` and `due` clauses, which can be given in either order.
+fn date_clauses(matches: &ArgMatches) -> (Option<&ArgMatches>, Option<&ArgMatches>) {
+    let mut when = None;
+    let mut due = None;
+    let mut current = matches;
+
+    while let Some((name, sub_matches)) = current.subcommand() {
+        match name {
+            "when" => when = Some(sub_matches),
+            "due" => due = Some(sub_matches),
+            _ => break,
+        }
+        current = sub_matches;
+    }
+
+    (when, due)
+}
+
+/// How `taiga list` orders and trims its output, on top of the filter words.
+struct ListOptions {
+    /// Field to sort by, one of the `--sort` values.
+    sort: String,
+    descending: bool,
+    /// `date` or `status` to print the tasks under headings.
+    group_by: Option<String>,
+    /// Only tasks created at least this long ago.
+    older_than: Option<chrono::Duration>,
+}
+
+/// Prints the tasks of `repo` matching `filter` as a tree, grouped if asked to.
+fn print_matching(repo: &TaskRepository, filter: &Filter, options: &ListOptions) {
+    let now = Local::now();
+    let mut tasks = filter.select(repo, now);
+    if let Some(min) = options.older_than {
+        tasks.retain(|task| task.age(now).is_some_and(|age| age >= min));
+    }
+
+    if tasks.is_empty() {
+        println!("No tasks found.");
+        return;
+    }
+
+    let Some(group_by) = &options.group_by else {
+        print_forest(repo, tasks, options, now);
+        return;
+    };
+
+    let blocked: HashSet<u32> = repo.blocked().iter().map(|t| t.id).collect();
+    let mut groups: Vec<(&str, Vec<&Task>)> = Vec::new();
+    for task in tasks {
+        let heading = if group_by == "status" {
+            status_group(task, &blocked, now)
+        } else {
+            date_group(task, now)
+        };
+        match groups.iter_mut().find(|(h, _)| *h == heading) {
+            Some((_, group)) => group.push(task),
+            None => groups.push((heading, vec![task])),
+        }
+    }
+
+    let order: &[&str] = if group_by == "status" {
+        &STATUS_GROUPS
+    } else {
+        &DATE_GROUPS
+    };
+    groups.sort_by_key(|(heading, _)| order.iter().position(|h| h == heading));
+    for (heading, group) in groups {
+        println!("== {} ==", heading);
+        print_forest(repo, group, options, now);
+    }
+}
+
+const DATE_GROUPS: [&str; 6] = [
+    "Overdue",
+    "Today",
+    "This week",
+    "Later",
+    "Earlier",
+    "Unscheduled",
+];
+const STATUS_GROUPS: [&str; 4] = ["Overdue", "Blocked", "Open", "Done"];
+
+/// Where a task goes with `--group-by date`, by its scheduled date or else its due date.
+fn date_group(task: &Task, now: DateTime<Local>) -> &'static str {
+    let Some(date) = task.scheduled.or(task.due) else {
+        return "Unscheduled";
+    };
+    let (day, today) = (date.date_naive(), now.date_naive());
+    // The coming seven days rather than the calendar week, so on a Sunday tomorrow
+    // doesn't count as "later".
+    let end_of_week = today + chrono::Days::new(6);
+
+    if task.is_overdue(now) || (day < today && !task.is_complete) {
+        "Overdue"
+    } else if day < today {
+        // Done and in the past, nothing to worry about.
+        "Earlier"
+    } else if day == today {
+        "Today"
+    } else if day <= end_of_week {
+        "This week"
+    } else {
+        "Later"
+    }
+}
+
+fn status_group(task: &Task, blocked: &HashSet<u32>, now: DateTime<Local>) -> &'static str {
+    if task.is_complete {
+        "Done"
+    } else if task.is_overdue(now) {
+        "Overdue"
+    } else if blocked.contains(&task.id) {
+        "Blocked"
+    } else {
+        "Open"
+    }
+}
+
+/// Sorts `tasks` and prints them, with subtasks under their parent when it is shown too.
+fn print_forest(
+    repo: &TaskRepository,
+    mut tasks: Vec<&Task>,
+    options: &ListOptions,
+    now: DateTime<Local>,
+) {
+    sort_tasks(&mut tasks, options);
+    let visible: HashSet<u32> = tasks.iter().map(|t| t.id).collect();
+    for task in tasks {
+        if task.parent.is_none_or(|p| !visible.contains(&p)) {
+            print_tree(repo, task, &visible, options, now, 0);
+        }
+    }
+}
+
+/// The tasks a bulk command applies to: the given IDs and ranges, or `--filter`.
+///
+/// Prints the error and returns `None` if the filter doesn't parse.
+fn target_ids(repo: &TaskRepository, matches: &ArgMatches) -> Option<Vec<u32>> {
+    if let Some(filter) = matches.get_one::<String>("FILTER") {
+        return filter_ids(repo, filter);
+    }
+
+    let ranges: Vec<IdRange> = matches
+        .get_many::<IdRange>("ID")
+        .map(|ranges| ranges.copied().collect())
+        .unwrap_or_default();
+    let known: Vec<u32> = repo.tasks.keys().copied().collect();
+    Some(IdRange::resolve(&ranges, &known))
+}
+
+fn filter_ids(repo: &TaskRepository, query: &str) -> Option<Vec<u32>> {
+    let now = Local::now();
+    match Filter::parse(query, now) {
+        Ok(filter) => Some(
+            filter
+                .select(repo, now)
+                .iter()
+                .map(|task| task.id)
+                .collect(),
+        ),
+        Err(e) => {
+            println!("Error: {}", e);
+            None
+        }
+    }
+}
+
+fn format_ids(ids: &[u32]) -> String {
+    ids.iter()
+        .map(|id| format!("#{}", id))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn sort_tasks(tasks: &mut [&Task], options: &ListOptions) {
+    let descending = options.descending;
+    tasks.sort_by(|a, b| {
+        let order = match options.sort.as_str() {
+            "id" => a.id.cmp(&b.id),
+            "title" => a.title.to_lowercase().cmp(&b.title.to_lowercase()),
+            "scheduled" => dates_last(a.scheduled, b.scheduled, descending),
+            "due" => dates_last(a.due, b.due, descending),
+            // Tasks without a creation time are older than anything recorded.
+            "created" => a.created_at.cmp(&b.created_at),
+            _ => a.priority_key().cmp(&b.priority_key()),
+        };
+        let order = match options.sort.as_str() {
+            // Already ordered the right way round.
+            "scheduled" | "due" => order,
+            _ if descending => order.reverse(),
+            _ => order,
+        };
+        order.then(a.id.cmp(&b.id))
+    });
+}
+
+/// Compares optional dates with the undated ones last, whichever the direction.
+fn dates_last(
+    a: Option<DateTime<Local>>,
+    b: Option<DateTime<Local>>,
+    descending: bool,
+) -> std::cmp::Ordering {
+    match (a, b) {
+        (Some(a), Some(b)) if descending => b.cmp(&a),
+        (Some(a), Some(b)) => a.cmp(&b),
+        _ => b.is_none().cmp(&a.is_none()).reverse(),
+    }
+}
+
+fn print_tree(
+    repo: &TaskRepository,
+    task: &Task,
+    visible: &HashSet<u32>,
+    options: &ListOptions,
+    now: DateTime<Local>,
+    depth: usize,
+) {
+    let age = match task.age(now) {
+        Some(age) if !task.is_complete => format!(" - {}", format_age(age)),
+        _ => String::new(),
+    };
+    println!("{}{}{}", "  ".repeat(depth), task.to_md_line(), age);
+
+    let mut children = repo.children(task.id);
+    children.retain(|child| visible.contains(&child.id));
+    sort_tasks(&mut children, options);
+    for child in children {
+        print_tree(repo, child, visible, options, now, depth + 1);
+    }
+}
+
+/// Short relative age such as "3d old".
+fn format_age(age: chrono::Duration) -> String {
+    if age.num_days() > 0 {
+        format!("{}d old", age.num_days())
+    } else if age.num_hours() > 0 {
+        format!("{}h old", age.num_hours())
+    } else {
+        format!("{}m old", age.num_minutes().max(0))
+    }
+}
+
+/// Scheduled and due dates from a command's `when` and `due` clauses.
+type Dates = (Option<DateTime<Local>>, Option<DateTime<Local>>);
+
+/// The `when` and `due` dates given to `matches`, or `None` after reporting one
+/// that couldn't be understood.
+fn parse_date_clauses(matches: &ArgMatches) -> Option<Dates> {
+    let (when_matches, due_matches) = date_clauses(matches);
+    let scheduled = match when_matches.map(|m| parse_date_clause(m, "SCHEDULED")) {
+        Some(None) => {
+            println!("Error: Could not understand the scheduled date.");
+            return None;
+        }
+        parsed => parsed.flatten(),
+    };
+    let due = match due_matches.map(|m| parse_date_clause(m, "DUE")) {
+        Some(None) => {
+            println!("Error: Could not understand the due date.");
+            return None;
+        }
+        parsed => parsed.flatten(),
+    };
+    Some((scheduled, due))
+}
+
+fn parse_date_clause(matches: &ArgMatches, arg: &str) -> Option<DateTime<Local>> {
+    let date_str = matches
+        .get_many::<String>(arg)?
+        .map(|s| s.as_str())
+        .collect::<Vec<_>>()
+        .join(" ");
+    dates::parse_natural(&date_str, Local::now())
+}
+
+/// Opens the task's Markdown line in `$VISUAL`/`$EDITOR` and parses it back.
+///
+/// The ID is not editable; whatever the user writes there is ignored.
+fn edit_in_editor(task: &Task) -> Result<Task, Box<dyn std::error::Error>> {
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".to_string());
+
+    let mut path = std::env::temp_dir();
+    path.push(format!("taiga-edit-{}-{}.md", std::process::id(), task.id));
+    std::fs::write(&path, task.to_md_line())?;
+
+    let mut parts = editor.split_whitespace();
+    let program = parts.next().ok_or("$EDITOR is empty")?;
+    let status = std::process::Command::new(program)
+        .args(parts)
+        .arg(&path)
+        .status();
+
+    let contents = std::fs::read_to_string(&path);
+    std::fs::remove_file(&path).ok();
+
+    if !status?.success() {
+        return Err(format!("Editor '{}' exited with an error", editor).into());
+    }
+
+    let contents = contents?;
+    let line = contents
+        .lines()
+        .find(|l| !l.trim().is_empty())
+        .ok_or("Edited task is empty, nothing changed")?;
+
+    let mut edited = Task::from_md_line(line.trim_end())
+        .ok_or("Could not parse the edited task, nothing changed")?;
+    edited.id = task.id;
+    Ok(edited)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn day(d: u32) -> DateTime<Local> {
+        Local.with_ymd_and_hms(2024, 3, d, 0, 0, 0).unwrap()
+    }
+
+    fn task(id: u32, scheduled: Option<DateTime<Local>>) -> Task {
+        let mut task = Task::new(format!("Task {}", id)).scheduled(scheduled);
+        task.id = id;
+        task
+    }
+
+    fn options(sort: &str, descending: bool) -> ListOptions {
+        ListOptions {
+            sort: sort.to_string(),
+            descending,
+            group_by: None,
+            older_than: None,
+        }
+    }
+
+    #[test]
+    fn date_groups_look_seven_days_ahead() {
+        // Sunday the 24th: the coming week runs to Saturday the 30th.
+        let now = Local.with_ymd_and_hms(2024, 3, 24, 14, 0, 0).unwrap();
+        assert_eq!(date_group(&task(1, None), now), "Unscheduled");
+        assert_eq!(date_group(&task(2, Some(day(24))), now), "Today");
+        assert_eq!(date_group(&task(3, Some(day(25))), now), "This week");
+        assert_eq!(date_group(&task(4, Some(day(30))), now), "This week");
+        assert_eq!(date_group(&task(5, Some(day(31))), now), "Later");
+
+        // Past days are overdue while open, and just history once done.
+        let mut past = task(6, Some(day(20)));
+        assert_eq!(date_group(&past, now), "Overdue");
+        past.complete(now);
+        assert_eq!(date_group(&past, now), "Earlier");
+
+        // Due today isn't overdue until the day is over.
+        let due_today = task(7, None).due(Some(day(24)));
+        assert_eq!(date_group(&due_today, now), "Today");
+    }
+
+    #[test]
+    fn status_groups_put_overdue_before_blocked() {
+        let now = Local.with_ymd_and_hms(2024, 3, 20, 14, 0, 0).unwrap();
+        let blocked: HashSet<u32> = [2, 3].into();
+
+        let mut done = task(1, None);
+        done.complete(now);
+        assert_eq!(status_group(&done, &blocked, now), "Done");
+        let late = task(2, None).due(Some(day(19)));
+        assert_eq!(status_group(&late, &blocked, now), "Overdue");
+        assert_eq!(status_group(&task(3, None), &blocked, now), "Blocked");
+        assert_eq!(status_group(&task(4, None), &blocked, now), "Open");
+    }
+
+    #[test]
+    fn undated_tasks_sort_last_either_way() {
+        let tasks = [
+            task(1, Some(day(21))),
+            task(2, None),
+            task(3, Some(day(19))),
+            task(4, None),
+        ];
+        let sorted = |sort: &str, descending: bool| {
+            let mut list: Vec<&Task> = tasks.iter().collect();
+            sort_tasks(&mut list, &options(sort, descending));
+            list.iter().map(|t| t.id).collect::<Vec<_>>()
+        };
+
+        assert_eq!(sorted("scheduled", false), [3, 1, 2, 4]);
+        assert_eq!(sorted("scheduled", true), [1, 3, 2, 4]);
+        assert_eq!(sorted("id", true), [4, 3, 2, 1]);
+    }
+
+    #[test]
+    fn default_sort_is_by_priority_then_id() {
+        let tasks = [
+            task(1, None),
+            task(2, None).priority(Some(Priority::Low)),
+            task(3, None).priority(Some(Priority::High)),
+            task(4, None).priority(Some(Priority::Low)),
+        ];
+        let mut list: Vec<&Task> = tasks.iter().collect();
+        sort_tasks(&mut list, &options("priority", false));
+        assert_eq!(list.iter().map(|t| t.id).collect::<Vec<_>>(), [3, 2, 4, 1]);
+    }
+}
@@ -0,0 +1,269 @@
+use chrono::{DateTime, Datelike, Days, Duration, Local, Months, Weekday};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// How often a recurring task comes back.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Recurrence {
+    Days(u32),
+    Weeks(u32),
+    Months(u32),
+    /// Every given day of the week, e.g. "every monday".
+    Weekday(Weekday),
+    /// A fixed day of every month, e.g. "first of the month".
+    MonthDay(u32),
+}
+
+impl Recurrence {
+    /// The first occurrence strictly after `from`, keeping its time of day.
+    pub fn next_after(&self, from: DateTime<Local>) -> DateTime<Local> {
+        match *self {
+            Recurrence::Days(n) => add_days(from, n),
+            Recurrence::Weeks(n) => add_days(from, 7 * n),
+            Recurrence::Months(n) => from
+                .checked_add_months(Months::new(n))
+                .unwrap_or_else(|| add_days(from, 30 * n)),
+            Recurrence::Weekday(weekday) => {
+                let ahead = (weekday.num_days_from_monday() + 7
+                    - from.weekday().num_days_from_monday())
+                    % 7;
+                let ahead = if ahead == 0 { 7 } else { ahead };
+                add_days(from, ahead)
+            }
+            Recurrence::MonthDay(day) => {
+                let mut next = add_days(from, 1);
+                // Months shorter than `day` use their last day instead of being skipped.
+                while next.day() != day.min(days_in_month(next)) {
+                    next = add_days(next, 1);
+                }
+                next
+            }
+        }
+    }
+
+    /// The next occurrence after `from` that is also in the future.
+    ///
+    /// Completing a daily chore a week late shouldn't leave a pile of past-due copies.
+    pub fn next_upcoming(&self, from: DateTime<Local>, now: DateTime<Local>) -> DateTime<Local> {
+        let mut next = self.next_after(from);
+        while next <= now {
+            next = self.next_after(next);
+        }
+        next
+    }
+}
+
+/// Calendar days rather than 24h steps, so a midnight stays a midnight across DST.
+fn add_days(from: DateTime<Local>, days: u32) -> DateTime<Local> {
+    from.checked_add_days(Days::new(days as u64))
+        .unwrap_or(from + Duration::days(days as i64))
+}
+
+fn days_in_month(date: DateTime<Local>) -> u32 {
+    let (year, month) = (date.year(), date.month());
+    let first_of_next = if month == 12 {
+        chrono::NaiveDate::from_ymd_opt(year + 1, 1, 1)
+    } else {
+        chrono::NaiveDate::from_ymd_opt(year, month + 1, 1)
+    };
+    first_of_next
+        .and_then(|d| d.pred_opt())
+        .map(|d| d.day())
+        .unwrap_or(28)
+}
+
+impl fmt::Display for Recurrence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Recurrence::Days(1) => write!(f, "every day"),
+            Recurrence::Days(n) => write!(f, "every {} days", n),
+            Recurrence::Weeks(1) => write!(f, "every week"),
+            Recurrence::Weeks(n) => write!(f, "every {} weeks", n),
+            Recurrence::Months(1) => write!(f, "every month"),
+            Recurrence::Months(n) => write!(f, "every {} months", n),
+            Recurrence::Weekday(weekday) => write!(f, "every {}", weekday_name(weekday)),
+            Recurrence::MonthDay(day) => write!(f, "every {}{} of the month", day, suffix(day)),
+        }
+    }
+}
+
+impl FromStr for Recurrence {
+    type Err = String;
+
+    /// Parses rules like "every monday", "every 2 weeks", "daily" or "first of the month".
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || format!("Don't know how to repeat '{}'", s.trim());
+
+        let lower = s.trim().to_lowercase();
+        let rule = lower.strip_prefix("every ").unwrap_or(&lower).trim();
+
+        match rule {
+            "day" | "daily" => return Ok(Recurrence::Days(1)),
+            "week" | "weekly" => return Ok(Recurrence::Weeks(1)),
+            "month" | "monthly" => return Ok(Recurrence::Months(1)),
+            "year" | "yearly" => return Ok(Recurrence::Months(12)),
+            _ => {}
+        }
+
+        if let Some(day) = rule
+            .strip_suffix("of the month")
+            .or_else(|| rule.strip_suffix("of every month"))
+        {
+            let day = parse_ordinal(day.trim()).ok_or_else(err)?;
+            return if (1..=31).contains(&day) {
+                Ok(Recurrence::MonthDay(day))
+            } else {
+                Err(err())
+            };
+        }
+
+        let words: Vec<&str> = rule.split_whitespace().collect();
+        match words.as_slice() {
+            [weekday] => parse_weekday(weekday)
+                .map(Recurrence::Weekday)
+                .ok_or_else(err),
+            [count, unit] => {
+                let n: u32 = count.parse().map_err(|_| err())?;
+                if n == 0 {
+                    return Err(err());
+                }
+                match unit.trim_end_matches('s') {
+                    "day" => Ok(Recurrence::Days(n)),
+                    "week" => Ok(Recurrence::Weeks(n)),
+                    "month" => Ok(Recurrence::Months(n)),
+                    "year" => Ok(Recurrence::Months(12 * n)),
+                    _ => Err(err()),
+                }
+            }
+            _ => Err(err()),
+        }
+    }
+}
+
+fn parse_weekday(word: &str) -> Option<Weekday> {
+    // Accept "mondays" as well as "monday".
+    let word = word
+        .strip_suffix('s')
+        .filter(|w| w.ends_with("day"))
+        .unwrap_or(word);
+    word.parse::<Weekday>().ok()
+}
+
+fn weekday_name(weekday: Weekday) -> &'static str {
+    match weekday {
+        Weekday::Mon => "monday",
+        Weekday::Tue => "tuesday",
+        Weekday::Wed => "wednesday",
+        Weekday::Thu => "thursday",
+        Weekday::Fri => "friday",
+        Weekday::Sat => "saturday",
+        Weekday::Sun => "sunday",
+    }
+}
+
+fn parse_ordinal(word: &str) -> Option<u32> {
+    let word = word.strip_prefix("the ").unwrap_or(word);
+    match word {
+        "first" => Some(1),
+        "second" => Some(2),
+        "third" => Some(3),
+        "last" => Some(31),
+        _ => word
+            .trim_end_matches(|c: char| c.is_ascii_alphabetic())
+            .parse()
+            .ok(),
+    }
+}
+
+fn suffix(day: u32) -> &'static str {
+    match (day % 10, day % 100) {
+        (_, 11..=13) => "th",
+        (1, _) => "st",
+        (2, _) => "nd",
+        (3, _) => "rd",
+        _ => "th",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn at(y: i32, m: u32, d: u32) -> DateTime<Local> {
+        Local.with_ymd_and_hms(y, m, d, 9, 30, 0).unwrap()
+    }
+
+    #[test]
+    fn parses_rules_and_round_trips_through_display() {
+        let cases = [
+            ("daily", Recurrence::Days(1)),
+            ("every 3 days", Recurrence::Days(3)),
+            ("every week", Recurrence::Weeks(1)),
+            ("every 2 weeks", Recurrence::Weeks(2)),
+            ("Every Mondays", Recurrence::Weekday(Weekday::Mon)),
+            ("yearly", Recurrence::Months(12)),
+            ("first of the month", Recurrence::MonthDay(1)),
+            ("every 22nd of every month", Recurrence::MonthDay(22)),
+            ("last of the month", Recurrence::MonthDay(31)),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(input.parse::<Recurrence>(), Ok(expected), "{}", input);
+            assert_eq!(expected.to_string().parse::<Recurrence>(), Ok(expected));
+        }
+
+        for bad in [
+            "every 0 days",
+            "every fortnight",
+            "32nd of the month",
+            "every 2 lunes",
+        ] {
+            assert!(bad.parse::<Recurrence>().is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn next_after_clamps_to_the_end_of_short_months() {
+        // Leap year: January 31st plus a month is February 29th.
+        assert_eq!(
+            Recurrence::Months(1).next_after(at(2024, 1, 31)),
+            at(2024, 2, 29)
+        );
+        assert_eq!(
+            Recurrence::MonthDay(31).next_after(at(2024, 1, 31)),
+            at(2024, 2, 29)
+        );
+        assert_eq!(
+            Recurrence::MonthDay(31).next_after(at(2024, 2, 29)),
+            at(2024, 3, 31)
+        );
+        assert_eq!(
+            Recurrence::MonthDay(15).next_after(at(2024, 3, 20)),
+            at(2024, 4, 15)
+        );
+
+        // 2024-03-18 is a Monday; the same weekday means a week later.
+        let monday = Recurrence::Weekday(Weekday::Mon);
+        assert_eq!(monday.next_after(at(2024, 3, 18)), at(2024, 3, 25));
+        assert_eq!(monday.next_after(at(2024, 3, 20)), at(2024, 3, 25));
+    }
+
+    #[test]
+    fn next_upcoming_skips_occurrences_already_past() {
+        let now = Local.with_ymd_and_hms(2024, 3, 20, 12, 0, 0).unwrap();
+        assert_eq!(
+            Recurrence::Days(1).next_upcoming(at(2024, 3, 10), now),
+            at(2024, 3, 21)
+        );
+        assert_eq!(
+            Recurrence::Weeks(2).next_upcoming(at(2024, 3, 1), now),
+            at(2024, 3, 29)
+        );
+        // Not overdue yet: the next one after it is still the plain next step.
+        assert_eq!(
+            Recurrence::Days(1).next_upcoming(at(2024, 3, 25), now),
+            at(2024, 3, 26)
+        );
+    }
+}
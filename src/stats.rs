@@ -0,0 +1,173 @@
+use chrono::{DateTime, Datelike, Days, Local, NaiveDate};
+use std::collections::{BTreeSet, HashMap};
+
+use crate::history::{Outcome, Session};
+use crate::ipc::PomoMode;
+
+/// Focus time and finished pomodoros over some period.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Total {
+    pub focus_secs: u64,
+    pub pomodoros: u32,
+}
+
+/// Everything `taiga pomo stats` reports, worked out from the history log.
+#[derive(Debug, Default)]
+pub struct Stats {
+    pub today: Total,
+    pub this_week: Total,
+    pub this_month: Total,
+    pub all_time: Total,
+    /// Focus time per task, most first. `None` collects sessions without a task.
+    pub per_task: Vec<(Option<u32>, Total)>,
+    /// Days in a row, up to today, with at least one finished pomodoro. A streak
+    /// isn't broken until today is over.
+    pub current_streak: u32,
+    pub longest_streak: u32,
+    /// Share of focus intervals that ran their full length.
+    pub completion_rate: Option<f64>,
+}
+
+impl Total {
+    fn add(&mut self, session: &Session) {
+        self.focus_secs += session.duration_secs;
+        if session.outcome == Outcome::Completed {
+            self.pomodoros += 1;
+        }
+    }
+}
+
+pub fn compute(sessions: &[Session], now: DateTime<Local>) -> Stats {
+    let today = now.date_naive();
+    let week_start = today - Days::new(today.weekday().num_days_from_monday() as u64);
+    let month_start = today.with_day(1).unwrap_or(today);
+
+    let mut stats = Stats::default();
+    let mut per_task: HashMap<Option<u32>, Total> = HashMap::new();
+    let mut productive_days = BTreeSet::new();
+    let mut finished = 0;
+    let mut focus_intervals = 0;
+
+    for session in sessions
+        .iter()
+        .filter(|s| matches!(s.mode, PomoMode::Focus))
+    {
+        let day = session.start.date_naive();
+        stats.all_time.add(session);
+        if day == today {
+            stats.today.add(session);
+        }
+        if day >= week_start {
+            stats.this_week.add(session);
+        }
+        if day >= month_start {
+            stats.this_month.add(session);
+        }
+        per_task.entry(session.task_id).or_default().add(session);
+
+        focus_intervals += 1;
+        if session.outcome == Outcome::Completed {
+            finished += 1;
+            productive_days.insert(day);
+        }
+    }
+
+    stats.per_task = per_task.into_iter().collect();
+    stats
+        .per_task
+        .sort_by_key(|(id, total)| (std::cmp::Reverse(total.focus_secs), *id));
+    stats.completion_rate = (focus_intervals > 0).then(|| finished as f64 / focus_intervals as f64);
+    (stats.current_streak, stats.longest_streak) = streaks(&productive_days, today);
+    stats
+}
+
+/// Focus time for each of the `days` days up to and including today, oldest first.
+pub fn daily_focus(sessions: &[Session], now: DateTime<Local>, days: u32) -> Vec<(NaiveDate, u64)> {
+    let today = now.date_naive();
+    (0..days as u64)
+        .rev()
+        .map(|ago| {
+            let day = today - Days::new(ago);
+            let secs = sessions
+                .iter()
+                .filter(|s| matches!(s.mode, PomoMode::Focus) && s.start.date_naive() == day)
+                .map(|s| s.duration_secs)
+                .sum();
+            (day, secs)
+        })
+        .collect()
+}
+
+fn streaks(days: &BTreeSet<NaiveDate>, today: NaiveDate) -> (u32, u32) {
+    let mut longest = 0;
+    let mut run = 0;
+    let mut previous: Option<NaiveDate> = None;
+    for day in days {
+        run = match previous {
+            Some(p) if p.succ_opt() == Some(*day) => run + 1,
+            _ => 1,
+        };
+        longest = longest.max(run);
+        previous = Some(*day);
+    }
+
+    let mut current = 0;
+    let mut day = if days.contains(&today) {
+        Some(today)
+    } else {
+        today.pred_opt()
+    };
+    while let Some(d) = day.filter(|d| days.contains(d)) {
+        current += 1;
+        day = d.pred_opt();
+    }
+    (current, longest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn focus(day: u32, minutes: u64, outcome: Outcome, task_id: Option<u32>) -> Session {
+        let start = Local.with_ymd_and_hms(2024, 3, day, 9, 0, 0).unwrap();
+        Session {
+            mode: PomoMode::Focus,
+            start,
+            end: start + chrono::Duration::minutes(minutes as i64),
+            duration_secs: minutes * 60,
+            task_id,
+            outcome,
+        }
+    }
+
+    #[test]
+    fn totals_streaks_and_completion_rate() {
+        // Wednesday the 20th; the week started on Monday the 18th.
+        let now = Local.with_ymd_and_hms(2024, 3, 20, 18, 0, 0).unwrap();
+        let sessions = vec![
+            focus(10, 25, Outcome::Completed, None),
+            focus(11, 25, Outcome::Completed, Some(1)),
+            focus(12, 25, Outcome::Completed, Some(1)),
+            focus(18, 25, Outcome::Completed, Some(2)),
+            focus(19, 10, Outcome::Stopped, Some(2)),
+            focus(19, 25, Outcome::Completed, Some(1)),
+        ];
+
+        let stats = compute(&sessions, now);
+        assert_eq!(stats.today, Total::default());
+        assert_eq!(
+            stats.this_week,
+            Total {
+                focus_secs: 60 * 60,
+                pomodoros: 2
+            }
+        );
+        assert_eq!(stats.all_time.pomodoros, 5);
+        assert_eq!(stats.per_task[0].0, Some(1));
+        // Nothing today yet, but the streak from the 18th still counts.
+        assert_eq!(stats.current_streak, 2);
+        assert_eq!(stats.longest_streak, 3);
+        assert_eq!(stats.completion_rate, Some(5.0 / 6.0));
+    }
+}
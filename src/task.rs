@@ -1,91 +1,360 @@
-use chrono::{DateTime, Local, NaiveDate, TimeZone};
+use chrono::{DateTime, Local, SecondsFormat};
 use regex::Regex;
+
+use crate::dates::{self, format_md_date, parse_md_date};
+use crate::recurrence::Recurrence;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::fs::OpenOptions;
-use std::io::{BufRead, BufReader, Write};
-use std::path::PathBuf;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{LazyLock, mpsc};
+use std::thread;
+use std::time::Duration;
 
-#[derive(Serialize, Deserialize, Debug)]
+// Regex Breakdown:
+// ^\[ID:(\d+)\]      -> Starts with [ID:digits], capture digits (Group 1)
+// \s-\s              -> " - " separator
+// \[(.)\]            -> [x] or [ ], capture the character (Group 2)
+// \s                 -> space
+// (.*)               -> Title followed by optional metadata (Group 3)
+// Leading indentation marks a subtask; it is handled by the repository, not here.
+static TASK_LINE_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^\s*\[ID:(\d+)\] - \[(.)\] (.*)$").unwrap());
+
+/// Indentation written per subtask level.
+const INDENT: &str = "  ";
+
+// Metadata is appended as " (Key: value)" groups after the title, so we peel
+// them off from the end. Lines written before a key existed simply lack it, and
+// stray whitespace from hand edits is tolerated around the groups.
+static META_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"\s+\((Scheduled|Due|Priority|Repeat|Depends|Created|Completed|Deleted):\s*([^()]*?)\s*\)\s*$",
+    )
+    .unwrap()
+});
+
+// The ID counter is kept in an HTML comment on the first line so that it doesn't
+// show up when the file is rendered, e.g. `<!-- taiga:next-id=42 -->`.
+static NEXT_ID_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^<!-- taiga:next-id=(\d+) -->$").unwrap());
+
+static TAG_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?:^|\s)([+@])([\w\-/.]+)").unwrap());
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    High,
+    Medium,
+    Low,
+}
+
+impl fmt::Display for Priority {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Priority::High => "High",
+            Priority::Medium => "Medium",
+            Priority::Low => "Low",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for Priority {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // Accept both words and the A-C letter style some people use in their notes.
+        match s.trim().to_lowercase().as_str() {
+            "high" | "h" | "a" => Ok(Priority::High),
+            "medium" | "med" | "m" | "b" => Ok(Priority::Medium),
+            "low" | "l" | "c" => Ok(Priority::Low),
+            other => Err(format!(
+                "Unknown priority '{}' (expected high, medium or low)",
+                other
+            )),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Task {
     pub id: u32,
     pub title: String,
     pub is_complete: bool,
+    /// When the task is planned to be worked on.
     pub scheduled: Option<DateTime<Local>>,
+    /// When the task has to be done by.
+    pub due: Option<DateTime<Local>>,
+    pub priority: Option<Priority>,
+    /// `+project` tokens found in the title, without the leading `+`.
+    pub tags: Vec<String>,
+    /// `@context` tokens found in the title, without the leading `@`.
+    pub contexts: Vec<String>,
+    pub recurrence: Option<Recurrence>,
+    /// The task this one is a subtask of, taken from the indentation in the file.
+    pub parent: Option<u32>,
+    /// IDs of tasks that have to be done before this one can start.
+    pub depends_on: Vec<u32>,
+    /// When the task was checked off; cleared again when it is reopened.
+    pub completed_at: Option<DateTime<Local>>,
+    /// When the task was added. Tasks from before this was recorded have none.
+    pub created_at: Option<DateTime<Local>>,
+    /// When the task was moved to the trash. Only set on tasks in the trash file.
+    pub deleted_at: Option<DateTime<Local>>,
 }
 
 impl Task {
     pub fn new(title: String) -> Self {
+        let (tags, contexts) = extract_tags(&title);
         Self {
             id: 0,
             title,
             is_complete: false,
             scheduled: None,
+            due: None,
+            priority: None,
+            tags,
+            contexts,
+            recurrence: None,
+            parent: None,
+            depends_on: Vec::new(),
+            completed_at: None,
+            created_at: None,
+            deleted_at: None,
+        }
+    }
+
+    pub fn complete(&mut self, at: DateTime<Local>) {
+        self.is_complete = true;
+        self.completed_at = Some(at);
+    }
+
+    pub fn reopen(&mut self) {
+        self.is_complete = false;
+        self.completed_at = None;
+    }
+
+    /// Replaces the title and re-extracts its tags and contexts.
+    pub fn set_title(&mut self, title: String) {
+        let (tags, contexts) = extract_tags(&title);
+        self.title = title;
+        self.tags = tags;
+        self.contexts = contexts;
+    }
+
+    /// Time since the task was added, if known.
+    pub fn age(&self, now: DateTime<Local>) -> Option<chrono::Duration> {
+        self.created_at.map(|created| now - created)
+    }
+
+    /// Sort key putting higher priorities first and unprioritized tasks last.
+    pub fn priority_key(&self) -> (bool, Option<Priority>, u32) {
+        (self.priority.is_none(), self.priority, self.id)
+    }
+
+    /// Open and past its due date. A task due on a day (no time) isn't overdue until
+    /// that day is over.
+    pub fn is_overdue(&self, now: DateTime<Local>) -> bool {
+        match self.due {
+            Some(due) if dates::is_date_only(&due) => {
+                !self.is_complete && due.date_naive() < now.date_naive()
+            }
+            Some(due) => !self.is_complete && due < now,
+            None => false,
         }
     }
 
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|t| t.eq_ignore_ascii_case(tag))
+    }
+
+    pub fn has_context(&self, context: &str) -> bool {
+        self.contexts
+            .iter()
+            .any(|c| c.eq_ignore_ascii_case(context))
+    }
+
     pub fn scheduled(mut self, date: Option<DateTime<Local>>) -> Self {
         self.scheduled = date;
         self
     }
 
+    pub fn due(mut self, date: Option<DateTime<Local>>) -> Self {
+        self.due = date;
+        self
+    }
+
+    pub fn priority(mut self, priority: Option<Priority>) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    pub fn recurrence(mut self, recurrence: Option<Recurrence>) -> Self {
+        self.recurrence = recurrence;
+        self
+    }
+
+    pub fn parent(mut self, parent: Option<u32>) -> Self {
+        self.parent = parent;
+        self
+    }
+
+    pub fn depends_on(mut self, ids: Vec<u32>) -> Self {
+        self.depends_on = ids;
+        self
+    }
+
     pub fn to_md_line(&self) -> String {
         let check_mark = if self.is_complete { "x" } else { " " };
-        match &self.scheduled {
-            Some(dt) => format!(
-                "[ID:{}] - [{}] {} (Scheduled: {})\n",
-                self.id,
-                check_mark,
-                self.title,
-                dt.format("%Y-%m-%d")
-            ),
-            None => format!("[ID:{}] - [{}] {}\n", self.id, check_mark, self.title,),
+        let mut line = format!("[ID:{}] - [{}] {}", self.id, check_mark, self.title);
+
+        if let Some(priority) = self.priority {
+            line.push_str(&format!(" (Priority: {})", priority));
+        }
+        if let Some(dt) = &self.scheduled {
+            line.push_str(&format!(" (Scheduled: {})", format_md_date(dt)));
+        }
+        if let Some(dt) = &self.due {
+            line.push_str(&format!(" (Due: {})", format_md_date(dt)));
         }
+        if let Some(recurrence) = &self.recurrence {
+            line.push_str(&format!(" (Repeat: {})", recurrence));
+        }
+        if !self.depends_on.is_empty() {
+            let ids: Vec<String> = self.depends_on.iter().map(|id| id.to_string()).collect();
+            line.push_str(&format!(" (Depends: {})", ids.join(", ")));
+        }
+        if let Some(dt) = &self.created_at {
+            line.push_str(&format!(
+                " (Created: {})",
+                dt.to_rfc3339_opts(SecondsFormat::Secs, false)
+            ));
+        }
+        if let Some(dt) = &self.completed_at {
+            line.push_str(&format!(
+                " (Completed: {})",
+                dt.to_rfc3339_opts(SecondsFormat::Secs, false)
+            ));
+        }
+        if let Some(dt) = &self.deleted_at {
+            line.push_str(&format!(
+                " (Deleted: {})",
+                dt.to_rfc3339_opts(SecondsFormat::Secs, false)
+            ));
+        }
+
+        line
     }
 
     pub fn from_md_line(line: &str) -> Option<Self> {
-        // Regex Breakdown:
-        // ^\[ID:(\d+)\]      -> Starts with [ID:digits], capture digits (Group 1)
-        // \s-\s              -> " - " separator
-        // \[(.)\]            -> [x] or [ ], capture the character (Group 2)
-        // \s                 -> space
-        // (.*?)              -> Capture the Title lazy (Group 3)
-        // (?:\s\(Scheduled:\s(.*)\))?$ -> Optional Non-capturing group for schedule. Capture date (Group 4)
-
-        let re = Regex::new(r"^\[ID:(\d+)\] - \[(.)\] (.*?)(?: \(Scheduled: (.*)\))?$").unwrap();
-
-        let caps = re.captures(line)?;
+        let caps = TASK_LINE_RE.captures(line)?;
 
         let id = caps.get(1)?.as_str().parse::<u32>().ok()?;
 
         let is_complete = caps.get(2)?.as_str() == "x";
 
-        let title = caps.get(3)?.as_str().to_string();
+        let mut rest = caps.get(3)?.as_str();
+        let mut scheduled = None;
+        let mut due = None;
+        let mut priority = None;
+        let mut recurrence = None;
+        let mut depends_on = Vec::new();
+        let mut created_at = None;
+        let mut completed_at = None;
+        let mut deleted_at = None;
 
-        let scheduled = match caps.get(4) {
-            Some(m) => {
-                let date_str = m.as_str();
-                NaiveDate::parse_from_str(date_str, "%Y-%m-%d")
-                    .ok()
-                    .and_then(|d| d.and_hms_opt(0, 0, 0))
-                    .and_then(|dt| Local.from_local_datetime(&dt).single())
+        while let Some(meta) = META_RE.captures(rest) {
+            let value = meta.get(2)?.as_str();
+            match meta.get(1)?.as_str() {
+                "Scheduled" => scheduled = parse_md_date(value),
+                "Due" => due = parse_md_date(value),
+                "Priority" => priority = value.parse::<Priority>().ok(),
+                "Repeat" => recurrence = value.parse::<Recurrence>().ok(),
+                "Created" => created_at = parse_md_date(value),
+                "Completed" => completed_at = parse_md_date(value),
+                "Deleted" => deleted_at = parse_md_date(value),
+                "Depends" => {
+                    depends_on = value
+                        .split(',')
+                        .filter_map(|id| id.trim().trim_start_matches('#').parse().ok())
+                        .collect();
+                }
+                _ => {}
             }
-            None => None,
-        };
+            rest = &rest[..meta.get(0)?.start()];
+        }
 
-        Some(Task {
-            id,
-            title,
-            is_complete,
-            scheduled,
-        })
+        let mut task = Task::new(rest.trim_end().to_string())
+            .scheduled(scheduled)
+            .due(due)
+            .priority(priority)
+            .recurrence(recurrence)
+            .depends_on(depends_on);
+        task.id = id;
+        task.is_complete = is_complete;
+        task.created_at = created_at;
+        // A timestamp on an unchecked line is stale (e.g. unticked by hand), drop it.
+        task.completed_at = completed_at.filter(|_| is_complete);
+        task.deleted_at = deleted_at;
+
+        Some(task)
     }
 }
 
+/// Splits `+project` and `@context` tokens out of a title.
+///
+/// The tokens stay in the title itself, which is what gets written to the file,
+/// so they survive a round trip without any extra metadata.
+fn extract_tags(title: &str) -> (Vec<String>, Vec<String>) {
+    let mut tags = Vec::new();
+    let mut contexts = Vec::new();
+
+    for caps in TAG_RE.captures_iter(title) {
+        let name = caps[2].to_string();
+        let list = if &caps[1] == "+" {
+            &mut tags
+        } else {
+            &mut contexts
+        };
+        if !list.contains(&name) {
+            list.push(name);
+        }
+    }
+
+    (tags, contexts)
+}
+
+/// How long `TaskRepository::lock` waits for another process before giving up.
+const LOCK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Guard returned by `TaskRepository::lock`; the lock is released when it is dropped.
+pub struct RepoLock {
+    _file: File,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct TaskRepository {
     pub tasks: HashMap<u32, Task>,
+    /// Never handed out twice, even after the task with the highest ID is gone.
     pub next_id: u32,
+    /// Whether the file had the ID counter header. Files from older versions don't,
+    /// and their IDs may have been reused already.
+    pub has_id_counter: bool,
+    /// The file as it was read, so headings and notes survive a save.
+    pub lines: Vec<Line>,
+}
+
+/// A single line of the task file.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum Line {
+    /// A task, rendered from `TaskRepository::tasks` on save.
+    Task(u32),
+    /// Anything else (headings, notes, blank lines), written back verbatim.
+    Text(String),
 }
 
 impl TaskRepository {
@@ -93,21 +362,68 @@ impl TaskRepository {
         TaskRepository {
             tasks: HashMap::new(),
             next_id: 1,
+            has_id_counter: false,
+            lines: Vec::new(),
         }
     }
 
-    pub fn add(&mut self, title: String, scheduled: Option<DateTime<Local>>) {
+    pub fn add(&mut self, mut task: Task) -> u32 {
         let id = self.next_id;
-
-        let task = Task {
-            id,
-            title,
-            is_complete: false,
-            scheduled,
-        };
+        task.id = id;
+        task.created_at.get_or_insert_with(Local::now);
 
         self.tasks.insert(id, task);
+        self.lines.push(Line::Task(id));
         self.next_id += 1;
+        id
+    }
+
+    /// Adds a task keeping its ID, e.g. when moving it between files.
+    pub fn insert(&mut self, task: Task) {
+        let id = task.id;
+        if self.tasks.insert(id, task).is_none() {
+            self.lines.push(Line::Task(id));
+        }
+        if id >= self.next_id {
+            self.next_id = id + 1;
+        }
+    }
+
+    /// Creates the next occurrence of recurring task `id` right below it.
+    ///
+    /// The repeat rule moves to the new task, so the completed one stays a plain
+    /// record and reopening it won't spawn a second copy.
+    pub fn schedule_next_occurrence(&mut self, id: u32) -> Option<u32> {
+        let current = self.tasks.get_mut(&id)?;
+        let recurrence = current.recurrence.take()?;
+
+        let now = Local::now();
+        // Unscheduled tasks repeat from today, keeping the next one date-only.
+        let from = current
+            .scheduled
+            .or_else(|| dates::start_of_day(now.date_naive()))
+            .unwrap_or(now);
+        let next = recurrence.next_upcoming(from, now);
+        let task = Task::new(current.title.clone())
+            .priority(current.priority)
+            .scheduled(Some(next))
+            .due(current.due.map(|due| recurrence.next_upcoming(due, now)))
+            .recurrence(Some(recurrence))
+            .parent(current.parent);
+
+        let new_id = self.add(task);
+
+        // Keep the new occurrence under the same heading as the old one.
+        self.lines.retain(|line| *line != Line::Task(new_id));
+        let position = self
+            .lines
+            .iter()
+            .position(|line| *line == Line::Task(id))
+            .map(|i| i + 1)
+            .unwrap_or(self.lines.len());
+        self.lines.insert(position, Line::Task(new_id));
+
+        Some(new_id)
     }
 
     pub fn get(&self, id: u32) -> Option<&Task> {
@@ -118,8 +434,116 @@ impl TaskRepository {
         self.tasks.get_mut(&id)
     }
 
+    /// Removes a task. Its subtasks move up to the removed task's parent.
     pub fn remove(&mut self, id: u32) -> Option<Task> {
-        self.tasks.remove(&id)
+        let task = self.tasks.remove(&id)?;
+        self.lines.retain(|line| *line != Line::Task(id));
+        for child in self.tasks.values_mut() {
+            if child.parent == Some(id) {
+                child.parent = task.parent;
+            }
+        }
+        Some(task)
+    }
+
+    /// Direct subtasks of `id`, ordered by ID.
+    pub fn children(&self, id: u32) -> Vec<&Task> {
+        let mut list: Vec<&Task> = self
+            .tasks
+            .values()
+            .filter(|t| t.parent == Some(id))
+            .collect();
+        list.sort_by_key(|t| t.id);
+        list
+    }
+
+    /// How many subtasks of `id`, at any depth, are still open.
+    pub fn open_subtasks(&self, id: u32) -> usize {
+        self.children(id)
+            .iter()
+            .map(|child| usize::from(!child.is_complete) + self.open_subtasks(child.id))
+            .sum()
+    }
+
+    /// Dependencies of `task` that exist and are still open.
+    pub fn open_dependencies(&self, task: &Task) -> Vec<u32> {
+        task.depends_on
+            .iter()
+            .copied()
+            .filter(|id| self.tasks.get(id).is_some_and(|dep| !dep.is_complete))
+            .collect()
+    }
+
+    /// Open but waiting on another open task. Links to removed tasks don't block.
+    pub fn is_blocked(&self, task: &Task) -> bool {
+        !task.is_complete && !self.open_dependencies(task).is_empty()
+    }
+
+    /// Open tasks waiting on at least one other open task.
+    pub fn blocked(&self) -> Vec<&Task> {
+        self.list_all()
+            .into_iter()
+            .filter(|t| self.is_blocked(t))
+            .collect()
+    }
+
+    /// Open tasks that can be started right now.
+    pub fn ready(&self) -> Vec<&Task> {
+        self.list_all()
+            .into_iter()
+            .filter(|t| !t.is_complete && !self.is_blocked(t))
+            .collect()
+    }
+
+    /// Makes `id` depend on `on`, refusing links that would create a cycle.
+    pub fn add_dependency(&mut self, id: u32, on: u32) -> Result<(), String> {
+        if !self.tasks.contains_key(&on) {
+            return Err(format!("Task #{} not found.", on));
+        }
+        if id == on || self.depends_transitively(on, id) {
+            return Err(format!(
+                "Task #{} already depends on #{}, linking them would create a cycle.",
+                on, id
+            ));
+        }
+
+        let task = self
+            .tasks
+            .get_mut(&id)
+            .ok_or_else(|| format!("Task #{} not found.", id))?;
+        if !task.depends_on.contains(&on) {
+            task.depends_on.push(on);
+        }
+        Ok(())
+    }
+
+    /// Whether `from` depends on `target`, directly or through other tasks.
+    fn depends_transitively(&self, from: u32, target: u32) -> bool {
+        let mut seen = HashSet::new();
+        let mut stack = vec![from];
+
+        while let Some(id) = stack.pop() {
+            if id == target {
+                return true;
+            }
+            if seen.insert(id)
+                && let Some(task) = self.tasks.get(&id)
+            {
+                stack.extend(task.depends_on.iter().copied());
+            }
+        }
+        false
+    }
+
+    /// Whether a task should be rendered at the top level rather than under a parent.
+    fn is_root(&self, task: &Task) -> bool {
+        task.parent
+            .is_none_or(|parent| !self.tasks.contains_key(&parent))
+    }
+
+    /// Makes sure IDs used in `other` (an archive or the trash) aren't handed out again.
+    pub fn reserve_ids(&mut self, other: &TaskRepository) {
+        self.next_id = self.next_id.max(other.next_id);
     }
 
     pub fn list_all(&self) -> Vec<&Task> {
@@ -128,6 +552,51 @@ impl TaskRepository {
         list
     }
 
+    /// Takes the advisory lock for the task file at `path`.
+    ///
+    /// Hold the returned guard for the whole load -> mutate -> save cycle so two taiga
+    /// processes can't both read the old file and then overwrite each other's changes.
+    /// If another process holds the lock we wait a little before giving up.
+    pub fn lock(path: &Path) -> Result<RepoLock, Box<dyn std::error::Error>> {
+        if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+
+        // The task file itself is replaced on every save, so the lock lives in its own file.
+        let lock_path = sibling_path(path, ".", ".lock");
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&lock_path)?;
+
+        match file.try_lock() {
+            Ok(()) => return Ok(RepoLock { _file: file }),
+            Err(TryLockError::WouldBlock) => {}
+            Err(TryLockError::Error(e)) => return Err(e.into()),
+        }
+
+        // Someone else is mid-save. Block on the lock in a helper thread so we can give
+        // up after a while; if we do, the thread drops the file (and the lock) once it
+        // finally gets it.
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let result = file.lock().map(|()| file);
+            tx.send(result).ok();
+        });
+
+        match rx.recv_timeout(LOCK_TIMEOUT) {
+            Ok(Ok(file)) => Ok(RepoLock { _file: file }),
+            Ok(Err(e)) => Err(e.into()),
+            Err(_) => Err(format!(
+                "Task file {} is locked by another taiga process, try again",
+                path.display()
+            )
+            .into()),
+        }
+    }
+
     pub fn load_from_file(
         file_path: &PathBuf,
     ) -> Result<TaskRepository, Box<dyn std::error::Error>> {
@@ -140,34 +609,379 @@ impl TaskRepository {
         let file = std::fs::File::open(file_path)?;
         let reader = BufReader::new(file);
 
+        // Indentation and ID of the tasks enclosing the current line.
+        let mut ancestors: Vec<(usize, u32)> = Vec::new();
+
         for line in reader.lines() {
             let line: String = line?;
-            if line.trim().is_empty() {
+
+            if let Some(caps) = NEXT_ID_RE.captures(&line) {
+                // A hand-added task may already be past the counter; keep the larger.
+                let next_id: u32 = caps[1].parse()?;
+                repo.next_id = repo.next_id.max(next_id);
+                repo.has_id_counter = true;
                 continue;
             }
 
-            if let Some(task) = Task::from_md_line(&line) {
-                if task.id >= repo.next_id {
-                    repo.next_id = task.id + 1;
+            match Task::from_md_line(&line) {
+                // A duplicated ID can't be told apart from the first one, so keep it as text
+                // rather than silently dropping either.
+                Some(mut task) if !repo.tasks.contains_key(&task.id) => {
+                    let indent = line.len() - line.trim_start().len();
+                    while ancestors.last().is_some_and(|(i, _)| *i >= indent) {
+                        ancestors.pop();
+                    }
+                    task.parent = ancestors.last().map(|(_, id)| *id);
+                    ancestors.push((indent, task.id));
+
+                    if task.id >= repo.next_id {
+                        repo.next_id = task.id + 1;
+                    }
+                    repo.lines.push(Line::Task(task.id));
+                    repo.tasks.insert(task.id, task);
                 }
-                repo.tasks.insert(task.id, task);
+                _ => repo.lines.push(Line::Text(line)),
             }
         }
 
         Ok(repo)
     }
 
-    pub fn save_to_file(&self, path: &PathBuf) -> Result<(), Box<dyn std::error::Error>> {
-        let mut file = OpenOptions::new()
-            .write(true)
-            .create(true)
-            .truncate(true) // Overwrite file
-            .open(path)?;
+    /// Writes the repository atomically.
+    ///
+    /// The tasks go to a temp file next to `path` which is fsynced and then renamed
+    /// over the original, so a crash leaves either the old or the new file, never half
+    /// of one. The previous version is kept as `<file>.bak`.
+    pub fn save_to_file(&self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        replace_file(path, true, |writer| self.write_document(writer))
+    }
+
+    fn write_document(&self, writer: &mut impl Write) -> Result<(), Box<dyn std::error::Error>> {
+        writeln!(writer, "<!-- taiga:next-id={} -->", self.next_id)?;
 
+        // Tasks inserted straight into `tasks` have no line yet; don't lose them.
+        let mut lines = self.lines.clone();
         for task in self.list_all() {
-            writeln!(file, "{}", task.to_md_line())?;
+            if !lines.contains(&Line::Task(task.id)) {
+                lines.push(Line::Task(task.id));
+            }
         }
 
+        let positions: HashMap<u32, usize> = lines
+            .iter()
+            .enumerate()
+            .filter_map(|(i, line)| match line {
+                Line::Task(id) => Some((*id, i)),
+                Line::Text(_) => None,
+            })
+            .collect();
+
+        // Subtasks are written indented right under their parent, wherever they were.
+        for line in &lines {
+            match line {
+                Line::Task(id) => {
+                    if let Some(task) = self.tasks.get(id)
+                        && self.is_root(task)
+                    {
+                        self.write_subtree(writer, task, 0, &positions)?;
+                    }
+                }
+                Line::Text(text) => writeln!(writer, "{}", text)?,
+            }
+        }
         Ok(())
     }
+
+    fn write_subtree(
+        &self,
+        writer: &mut impl Write,
+        task: &Task,
+        depth: usize,
+        positions: &HashMap<u32, usize>,
+    ) -> std::io::Result<()> {
+        writeln!(writer, "{}{}", INDENT.repeat(depth), task.to_md_line())?;
+
+        let mut children = self.children(task.id);
+        children.sort_by_key(|t| positions.get(&t.id).copied().unwrap_or(usize::MAX));
+        for child in children {
+            self.write_subtree(writer, child, depth + 1, positions)?;
+        }
+        Ok(())
+    }
+}
+
+/// Replaces `path` with what `write` produces, atomically: it goes to a temp file
+/// next to `path` that is synced to disk and then renamed over it. With `backup`
+/// the previous version is kept as `<name>.bak`.
+pub fn replace_file(
+    path: &Path,
+    backup: bool,
+    write: impl FnOnce(&mut BufWriter<File>) -> Result<(), Box<dyn std::error::Error>>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // Hidden files like the journal don't need a second dot.
+    let hidden = path
+        .file_name()
+        .is_some_and(|n| n.to_string_lossy().starts_with('.'));
+    let tmp_path = sibling_path(path, if hidden { "" } else { "." }, ".tmp");
+
+    let written = (|| -> Result<(), Box<dyn std::error::Error>> {
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&tmp_path)?;
+        let mut writer = BufWriter::new(file);
+        write(&mut writer)?;
+        writer.flush()?;
+        writer.get_ref().sync_all()?;
+        Ok(())
+    })();
+    if let Err(e) = written {
+        fs::remove_file(&tmp_path).ok();
+        return Err(e);
+    }
+
+    if backup && path.exists() {
+        fs::copy(path, sibling_path(path, "", ".bak"))?;
+    }
+    fs::rename(&tmp_path, path)?;
+
+    // Persist the rename itself. Directories can't be opened for syncing on Windows.
+    #[cfg(unix)]
+    if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+        File::open(dir)?.sync_all()?;
+    }
+
+    Ok(())
+}
+
+/// Builds a path next to `path` with its file name wrapped in `prefix` and `suffix`,
+/// e.g. `tasks.md` -> `.tasks.md.tmp`.
+pub fn sibling_path(path: &Path, prefix: &str, suffix: &str) -> PathBuf {
+    let file_name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    path.with_file_name(format!("{}{}{}", prefix, file_name, suffix))
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    /// A `tasks.md` path in a fresh temp directory of its own; other modules' tests
+    /// use it too.
+    pub fn temp_task_file(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("taiga-test-{}-{}", std::process::id(), name));
+        fs::remove_dir_all(&dir).ok();
+        fs::create_dir_all(&dir).unwrap();
+        dir.join("tasks.md")
+    }
+
+    #[test]
+    fn concurrent_adds_are_not_lost() {
+        let path = temp_task_file("concurrent-adds");
+        let workers = 8;
+        let adds_per_worker = 10;
+
+        let handles: Vec<_> = (0..workers)
+            .map(|w| {
+                let path = path.clone();
+                thread::spawn(move || {
+                    for i in 0..adds_per_worker {
+                        let _lock = TaskRepository::lock(&path).unwrap();
+                        let mut repo = TaskRepository::load_from_file(&path).unwrap();
+                        repo.add(Task::new(format!("worker {} task {}", w, i)));
+                        repo.save_to_file(&path).unwrap();
+                    }
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let repo = TaskRepository::load_from_file(&path).unwrap();
+        assert_eq!(repo.tasks.len(), workers * adds_per_worker);
+        assert_eq!(repo.next_id as usize, workers * adds_per_worker + 1);
+
+        fs::remove_dir_all(path.parent().unwrap()).ok();
+    }
+
+    #[test]
+    fn priority_round_trips_through_markdown() {
+        for priority in [Priority::High, Priority::Medium, Priority::Low] {
+            let mut task = Task::new("Ship".to_string()).priority(Some(priority));
+            task.id = 1;
+            let line = task.to_md_line();
+            assert_eq!(line, format!("[ID:1] - [ ] Ship (Priority: {})", priority));
+
+            let parsed = Task::from_md_line(&line).unwrap();
+            assert_eq!(parsed.priority, Some(priority));
+            assert_eq!(parsed.title, "Ship");
+            assert_eq!(parsed.to_md_line(), line);
+        }
+    }
+
+    #[test]
+    fn lines_without_priority_still_load() {
+        let line = "[ID:3] - [x] Old task (Scheduled: 2024-03-18)";
+        let task = Task::from_md_line(line).unwrap();
+        assert_eq!(task.priority, None);
+        assert_eq!(task.title, "Old task");
+        assert_eq!(task.to_md_line(), line);
+    }
+
+    #[test]
+    fn metadata_survives_stray_whitespace() {
+        let task =
+            Task::from_md_line("[ID:1] - [ ] Ship (Priority: High) (Due: 2024-01-01) ").unwrap();
+        assert_eq!(task.title, "Ship");
+        assert_eq!(task.priority, Some(Priority::High));
+        assert!(task.due.is_some());
+
+        let task =
+            Task::from_md_line("[ID:2] - [ ] Pack  (Priority: Low)  (Due: 2024-01-01)").unwrap();
+        assert_eq!(task.title, "Pack");
+        assert_eq!(task.priority, Some(Priority::Low));
+        assert_eq!(
+            task.to_md_line(),
+            "[ID:2] - [ ] Pack (Priority: Low) (Due: 2024-01-01)"
+        );
+    }
+
+    #[test]
+    fn date_only_schedule_round_trips_as_date() {
+        let line = "[ID:1] - [ ] Pay rent (Due: 2024-03-20) (Scheduled: 2024-03-18)";
+        let task = Task::from_md_line(line).unwrap();
+
+        let scheduled = task.scheduled.unwrap();
+        assert!(dates::is_date_only(&scheduled));
+        assert_eq!(scheduled.format("%Y-%m-%d").to_string(), "2024-03-18");
+        assert_eq!(
+            task.to_md_line(),
+            "[ID:1] - [ ] Pay rent (Scheduled: 2024-03-18) (Due: 2024-03-20)"
+        );
+    }
+
+    #[test]
+    fn timed_schedule_keeps_its_instant_in_local_time() {
+        let written = DateTime::parse_from_rfc3339("2024-03-20T09:30:15+02:00").unwrap();
+        let line = "[ID:7] - [ ] Standup (Scheduled: 2024-03-20T09:30:15+02:00)";
+
+        let parsed = Task::from_md_line(line).unwrap();
+        let scheduled = parsed.scheduled.unwrap();
+        assert_eq!(scheduled, written);
+
+        // Saved again with the local offset, to the second.
+        let local = written
+            .with_timezone(&Local)
+            .format("%Y-%m-%dT%H:%M:%S%:z")
+            .to_string();
+        let resaved = parsed.to_md_line();
+        assert_eq!(
+            resaved,
+            format!("[ID:7] - [ ] Standup (Scheduled: {})", local)
+        );
+        assert_eq!(Task::from_md_line(&resaved).unwrap().to_md_line(), resaved);
+    }
+
+    #[test]
+    fn subtasks_are_nested_under_their_parent() {
+        let path = temp_task_file("subtasks");
+        fs::write(
+            &path,
+            "[ID:1] - [ ] Release\n  [ID:2] - [x] Tag\n  [ID:3] - [ ] Publish\n    [ID:4] - [ ] Announce\n[ID:5] - [ ] Other\n",
+        )
+        .unwrap();
+
+        let mut repo = TaskRepository::load_from_file(&path).unwrap();
+        assert_eq!(repo.get(2).unwrap().parent, Some(1));
+        assert_eq!(repo.get(4).unwrap().parent, Some(3));
+        assert_eq!(repo.get(5).unwrap().parent, None);
+        assert_eq!(repo.open_subtasks(1), 2);
+
+        let changelog = repo.add(Task::new("Changelog".to_string()).parent(Some(1)));
+        repo.get_mut(changelog).unwrap().created_at = None;
+        repo.save_to_file(&path).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "<!-- taiga:next-id=7 -->\n[ID:1] - [ ] Release\n  [ID:2] - [x] Tag\n  [ID:3] - [ ] Publish\n    [ID:4] - [ ] Announce\n  [ID:6] - [ ] Changelog\n[ID:5] - [ ] Other\n"
+        );
+
+        fs::remove_dir_all(path.parent().unwrap()).ok();
+    }
+
+    #[test]
+    fn dependency_cycles_are_rejected() {
+        let mut repo = TaskRepository::new();
+        let design = repo.add(Task::new("Design".to_string()));
+        let build = repo.add(Task::new("Build".to_string()));
+        let ship = repo.add(Task::new("Ship".to_string()));
+
+        repo.add_dependency(build, design).unwrap();
+        repo.add_dependency(ship, build).unwrap();
+        assert!(repo.add_dependency(design, ship).is_err());
+        assert!(repo.add_dependency(design, design).is_err());
+
+        let ready: Vec<u32> = repo.ready().iter().map(|t| t.id).collect();
+        let blocked: Vec<u32> = repo.blocked().iter().map(|t| t.id).collect();
+        assert_eq!(ready, vec![design]);
+        assert_eq!(blocked, vec![build, ship]);
+
+        let line = repo.get(ship).unwrap().to_md_line();
+        assert_eq!(Task::from_md_line(&line).unwrap().depends_on, vec![build]);
+    }
+
+    #[test]
+    fn save_keeps_notes_and_headings() {
+        let path = temp_task_file("keeps-notes");
+        let original = "<!-- taiga:next-id=3 -->\n\
+                        # Work\n\
+                        [ID:1] - [ ] Ship it\n\
+                        Remember to ping ops first.\n\
+                        \n\
+                        # Home\n\
+                        [ID:2] - [x] Water plants\n";
+        fs::write(&path, original).unwrap();
+
+        let mut repo = TaskRepository::load_from_file(&path).unwrap();
+        repo.save_to_file(&path).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), original);
+
+        repo.remove(1);
+        let soil = repo.add(Task::new("Buy soil".to_string()));
+        repo.get_mut(soil).unwrap().created_at = None;
+        repo.save_to_file(&path).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "<!-- taiga:next-id=4 -->\n\
+             # Work\n\
+             Remember to ping ops first.\n\
+             \n\
+             # Home\n\
+             [ID:2] - [x] Water plants\n\
+             [ID:3] - [ ] Buy soil\n"
+        );
+
+        fs::remove_dir_all(path.parent().unwrap()).ok();
+    }
+
+    #[test]
+    fn ids_are_not_reused_after_removal() {
+        let path = temp_task_file("stable-ids");
+        fs::write(&path, "[ID:1] - [ ] Keep\n[ID:2] - [ ] Drop\n").unwrap();
+
+        let mut repo = TaskRepository::load_from_file(&path).unwrap();
+        assert!(!repo.has_id_counter);
+        repo.remove(2);
+        repo.save_to_file(&path).unwrap();
+
+        let mut repo = TaskRepository::load_from_file(&path).unwrap();
+        assert!(repo.has_id_counter);
+        assert_eq!(repo.add(Task::new("New".to_string())), 3);
+
+        fs::remove_dir_all(path.parent().unwrap()).ok();
+    }
 }
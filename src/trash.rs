@@ -0,0 +1,194 @@
+use chrono::{DateTime, Local};
+use std::path::{Path, PathBuf};
+
+use crate::journal::Entry;
+use crate::task::{Task, TaskRepository};
+
+/// Trash file for `task_file`, e.g. `taiginator.trash.md` next to `taiginator.md`.
+pub fn trash_path(task_file: &Path) -> PathBuf {
+    let stem = task_file
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    task_file.with_file_name(format!("{}.trash.md", stem))
+}
+
+pub fn load(task_file: &Path) -> Result<TaskRepository, Box<dyn std::error::Error>> {
+    TaskRepository::load_from_file(&trash_path(task_file))
+}
+
+/// Moves `ids` from `repo` into the trash, stamped with `now`.
+///
+/// Like archiving, the trash is saved before the caller saves `repo`.
+pub fn trash_tasks(
+    repo: &mut TaskRepository,
+    ids: &[u32],
+    task_file: &Path,
+    now: DateTime<Local>,
+) -> Result<Vec<Task>, Box<dyn std::error::Error>> {
+    let mut trash = load(task_file)?;
+    let mut removed = Vec::new();
+    for id in ids {
+        if let Some(task) = repo.remove(*id) {
+            let task = deleted(task, now);
+            trash.insert(task.clone());
+            removed.push(task);
+        }
+    }
+    trash.save_to_file(&trash_path(task_file))?;
+    Ok(removed)
+}
+
+/// Takes task `id` out of the trash and back into `repo`, at the top level.
+///
+/// Fails if `repo` has meanwhile handed out the same ID to another task.
+pub fn restore(
+    repo: &mut TaskRepository,
+    id: u32,
+    task_file: &Path,
+) -> Result<Option<Task>, Box<dyn std::error::Error>> {
+    let mut trash = load(task_file)?;
+    if trash.get(id).is_none() {
+        return Ok(None);
+    }
+    if repo.get(id).is_some() {
+        return Err(format!("Task #{} is already in use, can't restore over it.", id).into());
+    }
+
+    let mut task = trash.remove(id).expect("checked above");
+    task.deleted_at = None;
+    task.parent = None;
+    repo.insert(task.clone());
+    trash.save_to_file(&trash_path(task_file))?;
+    Ok(Some(task))
+}
+
+/// Permanently deletes trashed tasks removed before `cutoff`, or all of them.
+///
+/// Returns how many were deleted.
+pub fn purge(
+    task_file: &Path,
+    cutoff: Option<DateTime<Local>>,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let path = trash_path(task_file);
+    if !path.exists() {
+        return Ok(0);
+    }
+
+    let mut trash = TaskRepository::load_from_file(&path)?;
+    let expired: Vec<u32> = trash
+        .list_all()
+        .into_iter()
+        .filter(|task| {
+            cutoff.is_none_or(|cutoff| task.deleted_at.is_none_or(|deleted| deleted < cutoff))
+        })
+        .map(|task| task.id)
+        .collect();
+
+    if !expired.is_empty() {
+        for id in &expired {
+            trash.remove(*id);
+        }
+        trash.save_to_file(&path)?;
+    }
+    Ok(expired.len())
+}
+
+/// Keeps the trash in step with an undone or redone journal entry.
+///
+/// Undoing a removal brings tasks back, so they leave the trash; redoing one sends
+/// them there again. Tasks that vanish on undo were added by the entry and are
+/// simply dropped.
+pub fn follow(
+    entry: &Entry,
+    undo: bool,
+    task_file: &Path,
+    now: DateTime<Local>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let path = trash_path(task_file);
+    let mut trash = TaskRepository::load_from_file(&path)?;
+    let mut changed = false;
+
+    for change in &entry.changes {
+        match change.sides(undo) {
+            (None, Some(_)) if undo => changed |= trash.remove(change.id).is_some(),
+            (Some(task), None) if !undo => {
+                trash.insert(deleted(task.clone(), now));
+                changed = true;
+            }
+            _ => {}
+        }
+    }
+
+    if changed {
+        trash.save_to_file(&path)?;
+    }
+    Ok(())
+}
+
+fn deleted(mut task: Task, now: DateTime<Local>) -> Task {
+    task.deleted_at = Some(now);
+    task.parent = None;
+    task
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::task::tests::temp_task_file;
+    use chrono::TimeZone;
+
+    fn day(d: u32) -> DateTime<Local> {
+        Local.with_ymd_and_hms(2024, 3, d, 12, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn restore_refuses_an_id_that_is_taken_again() {
+        let path = temp_task_file("trash-restore");
+        let mut repo = TaskRepository::new();
+        let id = repo.add(Task::new("Keep".to_string()));
+        trash_tasks(&mut repo, &[id], &path, day(1)).unwrap();
+
+        // Say the file was edited by hand and the ID reused.
+        let mut squatter = Task::new("Squatter".to_string());
+        squatter.id = id;
+        repo.insert(squatter);
+        assert!(restore(&mut repo, id, &path).is_err());
+        assert!(load(&path).unwrap().get(id).is_some());
+
+        repo.remove(id);
+        let restored = restore(&mut repo, id, &path).unwrap().unwrap();
+        assert_eq!(restored.title, "Keep");
+        assert_eq!(restored.deleted_at, None);
+        assert!(load(&path).unwrap().get(id).is_none());
+        assert!(restore(&mut repo, 99, &path).unwrap().is_none());
+
+        std::fs::remove_dir_all(path.parent().unwrap()).ok();
+    }
+
+    #[test]
+    fn purge_drops_tasks_removed_before_the_cutoff_or_everything() {
+        let path = temp_task_file("trash-purge");
+        assert_eq!(purge(&path, None).unwrap(), 0);
+
+        let mut repo = TaskRepository::new();
+        let old = repo.add(Task::new("Old".to_string()));
+        let recent = repo.add(Task::new("Recent".to_string()));
+        let newest = repo.add(Task::new("Newest".to_string()));
+        trash_tasks(&mut repo, &[old], &path, day(1)).unwrap();
+        trash_tasks(&mut repo, &[recent], &path, day(10)).unwrap();
+        trash_tasks(&mut repo, &[newest], &path, day(20)).unwrap();
+
+        // Retention: anything removed before the 5th goes.
+        assert_eq!(purge(&path, Some(day(5))).unwrap(), 1);
+        let trash = load(&path).unwrap();
+        assert!(trash.get(old).is_none());
+        assert!(trash.get(recent).is_some() && trash.get(newest).is_some());
+
+        // `taiga trash empty`.
+        assert_eq!(purge(&path, None).unwrap(), 2);
+        assert!(load(&path).unwrap().list_all().is_empty());
+
+        std::fs::remove_dir_all(path.parent().unwrap()).ok();
+    }
+}